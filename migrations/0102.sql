@@ -0,0 +1,130 @@
+-- Type 4: base station report
+CREATE TABLE ais_base_station_reports (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    year INT,
+    month INT,
+    day INT,
+    hour INT,
+    minute INT,
+    second INT,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    epfd_type TEXT,
+    raim BOOLEAN NOT NULL,
+    received_at TIMESTAMP DEFAULT NOW()
+);
+
+-- Type 5: static and voyage related data
+CREATE TABLE ais_static_voyage_data (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    ais_version INT,
+    imo_number BIGINT,
+    callsign TEXT,
+    vessel_name TEXT,
+    ship_type INT,
+    dimension_to_bow INT,
+    dimension_to_stern INT,
+    dimension_to_port INT,
+    dimension_to_starboard INT,
+    epfd_type TEXT,
+    eta_month INT,
+    eta_day INT,
+    eta_hour INT,
+    eta_minute INT,
+    draught DOUBLE PRECISION,
+    destination TEXT,
+    received_at TIMESTAMP DEFAULT NOW()
+);
+
+-- Types 18 and 19: Class B position reports (static fields only present for type 19)
+CREATE TABLE ais_class_b_position_reports (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    speed_over_ground DOUBLE PRECISION,
+    course_over_ground DOUBLE PRECISION,
+    true_heading INT,
+    timestamp INT,
+    raim BOOLEAN NOT NULL,
+    vessel_name TEXT,
+    ship_type INT,
+    dimension_to_bow INT,
+    dimension_to_stern INT,
+    dimension_to_port INT,
+    dimension_to_starboard INT,
+    received_at TIMESTAMP DEFAULT NOW()
+);
+
+-- Type 12 (addressed) and 14 (broadcast) safety related messages
+CREATE TABLE ais_safety_messages (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    dest_mmsi BIGINT,
+    seqno INT,
+    retransmit BOOLEAN,
+    text TEXT NOT NULL,
+    received_at TIMESTAMP DEFAULT NOW()
+);
+
+-- Type 21: aid-to-navigation report
+CREATE TABLE ais_aton_reports (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    aid_type TEXT,
+    name TEXT NOT NULL,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    dimension_to_bow INT,
+    dimension_to_stern INT,
+    dimension_to_port INT,
+    dimension_to_starboard INT,
+    epfd_type TEXT,
+    utc_second INT,
+    off_position BOOLEAN NOT NULL,
+    regional_reserved INT,
+    raim BOOLEAN NOT NULL,
+    virtual_aid BOOLEAN NOT NULL,
+    assigned_mode BOOLEAN NOT NULL,
+    status_page INT, -- IALA A-126 page of the regional bits, only page 7 is decoded
+    received_at TIMESTAMP DEFAULT NOW()
+);
+
+-- Type 24: static data report (part A carries the name, part B the rest)
+CREATE TABLE ais_static_data_reports (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    part CHAR(1) NOT NULL,
+    vessel_name TEXT,
+    ship_type INT,
+    vendor_id TEXT,
+    callsign TEXT,
+    dimension_to_bow INT,
+    dimension_to_stern INT,
+    dimension_to_port INT,
+    dimension_to_starboard INT,
+    received_at TIMESTAMP DEFAULT NOW()
+);
+
+-- Type 27: long range AIS broadcast
+CREATE TABLE ais_long_range_reports (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    navigation_status TEXT,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    speed_over_ground DOUBLE PRECISION,
+    course_over_ground DOUBLE PRECISION,
+    raim BOOLEAN NOT NULL,
+    gnss_position_status BOOLEAN NOT NULL,
+    received_at TIMESTAMP DEFAULT NOW()
+);
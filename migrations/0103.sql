@@ -0,0 +1,11 @@
+-- Keep the full kinematics of types 1-3, not just the position
+ALTER TABLE ais_position_reports
+    ADD COLUMN navigation_status TEXT,
+    ADD COLUMN rate_of_turn DOUBLE PRECISION, -- degrees per minute, negative to port
+    ADD COLUMN speed_over_ground DOUBLE PRECISION,
+    ADD COLUMN course_over_ground DOUBLE PRECISION,
+    ADD COLUMN true_heading INT,
+    ADD COLUMN position_accuracy BOOLEAN, -- true for DGPS quality (< 10 m)
+    ADD COLUMN timestamp INT, -- UTC second of the report
+    ADD COLUMN maneuver_indicator TEXT,
+    ADD COLUMN raim BOOLEAN;
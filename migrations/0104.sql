@@ -0,0 +1,8 @@
+-- Reports without a position fix are stored with NULL coordinates instead of 0,0
+ALTER TABLE ais_position_reports
+    ALTER COLUMN latitude DROP NOT NULL,
+    ALTER COLUMN longitude DROP NOT NULL;
+
+-- Rows written before this migration used 0,0 for "not available"
+UPDATE ais_position_reports SET latitude = NULL, longitude = NULL
+    WHERE latitude = 0 AND longitude = 0;
@@ -0,0 +1,9 @@
+-- All stations that heard the same payload within the deduplication window
+ALTER TABLE ais_position_reports ADD COLUMN heard_by TEXT[];
+ALTER TABLE ais_base_station_reports ADD COLUMN heard_by TEXT[];
+ALTER TABLE ais_static_voyage_data ADD COLUMN heard_by TEXT[];
+ALTER TABLE ais_class_b_position_reports ADD COLUMN heard_by TEXT[];
+ALTER TABLE ais_safety_messages ADD COLUMN heard_by TEXT[];
+ALTER TABLE ais_aton_reports ADD COLUMN heard_by TEXT[];
+ALTER TABLE ais_static_data_reports ADD COLUMN heard_by TEXT[];
+ALTER TABLE ais_long_range_reports ADD COLUMN heard_by TEXT[];
@@ -0,0 +1,54 @@
+-- One row per vessel, kept up to date from static and voyage data (types 5 and 24)
+CREATE TABLE vessels (
+    mmsi BIGINT PRIMARY KEY,
+    vessel_name TEXT,
+    callsign TEXT,
+    imo_number BIGINT,
+    ship_type INT,
+    dimension_to_bow INT,
+    dimension_to_stern INT,
+    dimension_to_port INT,
+    dimension_to_starboard INT,
+    epfd_type TEXT,
+    draught DOUBLE PRECISION,
+    destination TEXT,
+    eta_month INT,
+    eta_day INT,
+    eta_hour INT,
+    eta_minute INT,
+    first_seen TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL
+);
+
+-- Every change to a vessel's static or voyage data, one row per changed field
+CREATE TABLE vessel_history (
+    id SERIAL PRIMARY KEY,
+    mmsi BIGINT NOT NULL REFERENCES vessels (mmsi) ON DELETE CASCADE,
+    field TEXT NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    changed_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX vessel_history_mmsi_idx ON vessel_history (mmsi, changed_at);
+
+CREATE FUNCTION record_vessel_changes() RETURNS TRIGGER AS $$
+DECLARE
+    old_row JSONB := to_jsonb(OLD);
+    new_row JSONB := to_jsonb(NEW);
+    field TEXT;
+BEGIN
+    FOR field IN SELECT jsonb_object_keys(new_row) LOOP
+        IF field NOT IN ('mmsi', 'first_seen', 'last_seen')
+            AND old_row -> field IS DISTINCT FROM new_row -> field THEN
+            INSERT INTO vessel_history (mmsi, field, old_value, new_value, changed_at)
+            VALUES (NEW.mmsi, field, old_row ->> field, new_row ->> field, NEW.last_seen);
+        END IF;
+    END LOOP;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER vessels_history
+    AFTER UPDATE ON vessels
+    FOR EACH ROW EXECUTE FUNCTION record_vessel_changes();
@@ -0,0 +1,52 @@
+-- Decoded Message 21 regional status (page 7 of IALA A-126)
+ALTER TABLE ais_aton_reports ADD COLUMN racon_status TEXT;
+ALTER TABLE ais_aton_reports ADD COLUMN light_status TEXT;
+ALTER TABLE ais_aton_reports ADD COLUMN health_status TEXT;
+
+-- Latest report from every aid to navigation
+CREATE TABLE aton_status (
+    mmsi BIGINT PRIMARY KEY,
+    name TEXT NOT NULL,
+    aid_type TEXT,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    off_position BOOLEAN NOT NULL,
+    virtual_aid BOOLEAN NOT NULL,
+    status_page INT,
+    racon_status TEXT,
+    light_status TEXT,
+    health_status TEXT,
+    first_seen TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL
+);
+
+-- One row each time an aid's status changes, holding the status it changed to
+CREATE TABLE aton_status_history (
+    id SERIAL PRIMARY KEY,
+    mmsi BIGINT NOT NULL REFERENCES aton_status (mmsi) ON DELETE CASCADE,
+    off_position BOOLEAN NOT NULL,
+    racon_status TEXT,
+    light_status TEXT,
+    health_status TEXT,
+    changed_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX aton_status_history_mmsi_idx ON aton_status_history (mmsi, changed_at);
+
+CREATE FUNCTION record_aton_status_change() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT'
+        OR (OLD.off_position, OLD.racon_status, OLD.light_status, OLD.health_status)
+            IS DISTINCT FROM (NEW.off_position, NEW.racon_status, NEW.light_status, NEW.health_status) THEN
+        INSERT INTO aton_status_history
+            (mmsi, off_position, racon_status, light_status, health_status, changed_at)
+        VALUES
+            (NEW.mmsi, NEW.off_position, NEW.racon_status, NEW.light_status, NEW.health_status, NEW.last_seen);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER aton_status_history
+    AFTER INSERT OR UPDATE ON aton_status
+    FOR EACH ROW EXECUTE FUNCTION record_aton_status_change();
@@ -0,0 +1,17 @@
+-- AtoN alarms, so open ones and their acknowledgements survive a restart.
+-- A row is rewritten on every change to its alarm.
+CREATE TABLE aton_alarms (
+    id BIGINT PRIMARY KEY,
+    mmsi BIGINT NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    state TEXT NOT NULL,
+    station TEXT NOT NULL,
+    raised_at TIMESTAMP NOT NULL,
+    last_reported_at TIMESTAMP NOT NULL,
+    acknowledged_at TIMESTAMP,
+    cleared_at TIMESTAMP,
+    reports BIGINT NOT NULL
+);
+
+CREATE INDEX aton_alarms_mmsi_idx ON aton_alarms (mmsi, raised_at);
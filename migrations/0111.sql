@@ -0,0 +1,99 @@
+-- Types 6 (addressed) and 8 (broadcast) binary messages, decoded or not
+CREATE TABLE ais_binary_messages (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    dest_mmsi BIGINT,
+    dac INT NOT NULL,
+    fi INT NOT NULL,
+    application TEXT, -- Name of the application if we can decode it
+    data BYTEA,
+    station TEXT,
+    received_at TIMESTAMP DEFAULT NOW(),
+    heard_by TEXT[]
+);
+
+-- DAC 1 FI 31: IMO 289 meteorological and hydrographic data
+CREATE TABLE ais_met_hydro (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    position_accuracy BOOLEAN NOT NULL,
+    day INT,
+    hour INT,
+    minute INT,
+    wind_speed INT,
+    wind_gust INT,
+    wind_direction INT,
+    wind_gust_direction INT,
+    air_temperature DOUBLE PRECISION,
+    relative_humidity INT,
+    dew_point DOUBLE PRECISION,
+    air_pressure INT,
+    air_pressure_tendency INT, -- 0 steady, 1 decreasing, 2 increasing
+    visibility DOUBLE PRECISION,
+    visibility_greater_than BOOLEAN NOT NULL,
+    water_level DOUBLE PRECISION,
+    water_level_trend INT, -- 0 steady, 1 decreasing, 2 increasing
+    surface_current_speed DOUBLE PRECISION,
+    surface_current_direction INT,
+    current2_speed DOUBLE PRECISION,
+    current2_direction INT,
+    current2_depth INT,
+    current3_speed DOUBLE PRECISION,
+    current3_direction INT,
+    current3_depth INT,
+    wave_height DOUBLE PRECISION,
+    wave_period INT,
+    wave_direction INT,
+    swell_height DOUBLE PRECISION,
+    swell_period INT,
+    swell_direction INT,
+    sea_state INT,
+    water_temperature DOUBLE PRECISION,
+    precipitation_type INT,
+    salinity DOUBLE PRECISION,
+    ice BOOLEAN,
+    station TEXT,
+    received_at TIMESTAMP DEFAULT NOW(),
+    heard_by TEXT[]
+);
+
+-- DAC 1 FI 22: IMO 289 area notice, sub-areas as JSON
+CREATE TABLE ais_area_notices (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    linkage_id INT NOT NULL,
+    notice_type INT NOT NULL,
+    month INT,
+    day INT,
+    hour INT,
+    minute INT,
+    duration_minutes INT,
+    subareas JSONB NOT NULL,
+    station TEXT,
+    received_at TIMESTAMP DEFAULT NOW(),
+    heard_by TEXT[]
+);
+
+-- DAC 235/250 FI 10: AtoN monitoring data from the UK and Irish lighthouse authorities
+CREATE TABLE ais_aton_monitoring (
+    id SERIAL PRIMARY KEY,
+    message_type INT,
+    mmsi BIGINT NOT NULL,
+    dac INT NOT NULL,
+    analogue_internal DOUBLE PRECISION,
+    analogue_external1 DOUBLE PRECISION,
+    analogue_external2 DOUBLE PRECISION,
+    racon_status TEXT,
+    light_status TEXT,
+    health_status TEXT,
+    digital_inputs INT,
+    off_position BOOLEAN NOT NULL,
+    station TEXT,
+    received_at TIMESTAMP DEFAULT NOW(),
+    heard_by TEXT[]
+);
@@ -0,0 +1,13 @@
+-- Source station and receiver time from NMEA 4.x TAG blocks, when the feed sends them
+ALTER TABLE ais_position_reports ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_base_station_reports ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_static_voyage_data ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_class_b_position_reports ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_safety_messages ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_aton_reports ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_static_data_reports ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_long_range_reports ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_binary_messages ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_met_hydro ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_area_notices ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
+ALTER TABLE ais_aton_monitoring ADD COLUMN tag_source TEXT, ADD COLUMN tag_time TIMESTAMP;
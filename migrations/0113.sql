@@ -0,0 +1,19 @@
+-- Reception statistics of every station, one row per station per sample interval.
+-- Counters hold what happened during the interval, not running totals.
+CREATE TABLE station_stats (
+    id BIGSERIAL PRIMARY KEY,
+    station TEXT NOT NULL,
+    sampled_at TIMESTAMP NOT NULL,
+    state TEXT NOT NULL,
+    last_message_at TIMESTAMP,
+    messages BIGINT NOT NULL,
+    messages_by_type JSONB NOT NULL,
+    parse_errors BIGINT NOT NULL,
+    checksum_errors BIGINT NOT NULL,
+    reconnects BIGINT NOT NULL
+);
+
+CREATE INDEX station_stats_station_idx ON station_stats (station, sampled_at);
+
+-- Old station statistics are deleted by age, across all stations
+CREATE INDEX station_stats_sampled_at_idx ON station_stats (sampled_at);
@@ -0,0 +1,537 @@
+use super::bits::BitReader;
+use super::msg21::MonitoredStatus;
+use ais::messages::AisMessage;
+use serde::Serialize;
+
+// A type 6 or 8 message with its application data decoded where we know how
+#[derive(Debug)]
+pub struct BinaryMessage {
+    pub message_type: u8,
+    pub mmsi: u32,
+    pub dest_mmsi: Option<u32>, // Only for addressed messages
+    pub dac: u16,
+    pub fi: u8,
+    pub data: Vec<u8>,
+    pub application: Option<ApplicationData>,
+}
+
+impl BinaryMessage {
+    // The ais crate hands the application data over as bytes without its exact bit
+    // length, so it is decoded again from the armored payload
+    pub fn new(message: AisMessage, payload: &str, fill_bits: u8) -> Option<Self> {
+        let (mut message, header_bits) = match message {
+            AisMessage::BinaryAddressedMessage(ba) => (
+                Self {
+                    message_type: ba.message_type,
+                    mmsi: ba.mmsi,
+                    dest_mmsi: Some(ba.dest_mmsi),
+                    dac: ba.dac,
+                    fi: ba.fid,
+                    data: ba.data,
+                    application: None,
+                },
+                88,
+            ),
+            AisMessage::BinaryBroadcastMessage(bb) => (
+                Self {
+                    message_type: bb.message_type,
+                    mmsi: bb.mmsi,
+                    dest_mmsi: None,
+                    dac: bb.dac,
+                    fi: bb.fid,
+                    data: bb.data,
+                    application: None,
+                },
+                56,
+            ),
+            _ => return None,
+        };
+
+        let decoded = BitReader::new(payload, fill_bits).and_then(|mut bits| {
+            bits.seek(header_bits)?;
+            decode(message.dac, message.fi, &mut bits).transpose()
+        });
+        match decoded {
+            Ok(application) => message.application = application,
+            Err(e) => eprintln!(
+                "Failed to decode DAC {} FI {} from {}: {}",
+                message.dac, message.fi, message.mmsi, e
+            ),
+        }
+        Some(message)
+    }
+}
+
+// Application data carried by binary messages (types 6 and 8), by DAC/FI
+#[derive(Debug)]
+pub enum ApplicationData {
+    MetHydro(MetHydro),
+    AreaNotice(AreaNotice),
+    AtonMonitoring(AtonMonitoring),
+}
+
+type Decoder = fn(&mut BitReader) -> anyhow::Result<ApplicationData>;
+
+// Every application we know how to decode: (DAC, FI, name, decoder)
+const REGISTRY: &[(u16, u8, &str, Decoder)] = &[
+    (1, 22, "Area notice", decode_area_notice),
+    (
+        1,
+        31,
+        "Meteorological and hydrographic data",
+        decode_met_hydro,
+    ),
+    (235, 10, "AtoN monitoring data (UK)", decode_aton_monitoring),
+    (
+        250,
+        10,
+        "AtoN monitoring data (ROI)",
+        decode_aton_monitoring,
+    ),
+];
+
+// Name of the application behind a DAC/FI pair, if it's one we know
+pub fn application_name(dac: u16, fi: u8) -> Option<&'static str> {
+    REGISTRY
+        .iter()
+        .find(|(d, f, _, _)| *d == dac && *f == fi)
+        .map(|(_, _, name, _)| *name)
+}
+
+// Decodes the application data the reader is positioned at. None for DAC/FI pairs
+// that are not in the registry.
+pub fn decode(dac: u16, fi: u8, bits: &mut BitReader) -> Option<anyhow::Result<ApplicationData>> {
+    REGISTRY
+        .iter()
+        .find(|(d, f, _, _)| *d == dac && *f == fi)
+        .map(|(_, _, _, decoder)| decoder(bits))
+}
+
+// `value` unless it is outside `valid`, which these messages use for "not available"
+fn within<T: PartialOrd>(value: T, valid: std::ops::RangeInclusive<T>) -> Option<T> {
+    valid.contains(&value).then_some(value)
+}
+
+// Longitude (25 bits) and latitude (24 bits) in 1/1000 minute, as used by IMO 289
+fn read_position(bits: &mut BitReader) -> anyhow::Result<(Option<f32>, Option<f32>)> {
+    let lon = bits.read_i32(25)? as f32 / 60_000.0;
+    let lat = bits.read_i32(24)? as f32 / 60_000.0;
+    Ok((within(lon, -180.0..=180.0), within(lat, -90.0..=90.0)))
+}
+
+// IMO SN.1/Circ.289 meteorological and hydrographic data (DAC 1, FI 31)
+#[derive(Debug)]
+pub struct MetHydro {
+    pub longitude: Option<f32>,
+    pub latitude: Option<f32>,
+    pub position_accuracy: bool,
+    pub day: Option<u8>,
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub wind_speed: Option<u8>, // Knots, 10 minute average
+    pub wind_gust: Option<u8>,
+    pub wind_direction: Option<u16>,
+    pub wind_gust_direction: Option<u16>,
+    pub air_temperature: Option<f32>, // °C
+    pub relative_humidity: Option<u8>,
+    pub dew_point: Option<f32>,
+    pub air_pressure: Option<u16>,         // hPa
+    pub air_pressure_tendency: Option<u8>, // 0 steady, 1 decreasing, 2 increasing
+    pub visibility: Option<f32>,           // Nautical miles
+    pub visibility_greater_than: bool,     // Visibility is beyond the sensor's range
+    pub water_level: Option<f32>,          // Metres, relative to the local chart datum
+    pub water_level_trend: Option<u8>,     // 0 steady, 1 decreasing, 2 increasing
+    pub currents: [Current; 3],            // Surface current first
+    pub wave_height: Option<f32>,          // Significant wave height, metres
+    pub wave_period: Option<u8>,           // Seconds
+    pub wave_direction: Option<u16>,
+    pub swell_height: Option<f32>,
+    pub swell_period: Option<u8>,
+    pub swell_direction: Option<u16>,
+    pub sea_state: Option<u8>, // Beaufort scale
+    pub water_temperature: Option<f32>,
+    pub precipitation_type: Option<u8>, // WMO code table 4.201
+    pub salinity: Option<f32>,          // ‰
+    pub ice: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+pub struct Current {
+    pub speed: Option<f32>, // Knots
+    pub direction: Option<u16>,
+    pub depth: Option<u8>, // Metres; None for the surface current
+}
+
+fn read_current(bits: &mut BitReader, with_depth: bool) -> anyhow::Result<Current> {
+    let speed = within(bits.read_u32(8)?, 0..=250).map(|s| s as f32 / 10.0);
+    let direction = within(bits.read_u32(9)? as u16, 0..=359);
+    let depth = if with_depth {
+        within(bits.read_u8(5)?, 0..=30)
+    } else {
+        None
+    };
+    Ok(Current {
+        speed,
+        direction,
+        depth,
+    })
+}
+
+fn decode_met_hydro(bits: &mut BitReader) -> anyhow::Result<ApplicationData> {
+    let (longitude, latitude) = read_position(bits)?;
+    let position_accuracy = bits.read_bool()?;
+    let day = within(bits.read_u8(5)?, 1..=31);
+    let hour = within(bits.read_u8(5)?, 0..=23);
+    let minute = within(bits.read_u8(6)?, 0..=59);
+    let wind_speed = within(bits.read_u8(7)?, 0..=126);
+    let wind_gust = within(bits.read_u8(7)?, 0..=126);
+    let wind_direction = within(bits.read_u32(9)? as u16, 0..=359);
+    let wind_gust_direction = within(bits.read_u32(9)? as u16, 0..=359);
+    let air_temperature = within(bits.read_i32(11)?, -600..=600).map(|t| t as f32 / 10.0);
+    let relative_humidity = within(bits.read_u8(7)?, 0..=100);
+    let dew_point = within(bits.read_i32(10)?, -200..=500).map(|t| t as f32 / 10.0);
+    // 0 means 799 hPa or less and 402 means 1201 hPa or more
+    let air_pressure = within(bits.read_u32(9)? as u16, 0..=402).map(|p| p + 799);
+    let air_pressure_tendency = within(bits.read_u8(2)?, 0..=2);
+    let greater_than = bits.read_bool()?;
+    let visibility = within(bits.read_u8(7)?, 0..=126).map(|v| v as f32 / 10.0);
+    let water_level = within(bits.read_u32(12)?, 0..=4000).map(|l| l as f32 / 100.0 - 10.0);
+    let water_level_trend = within(bits.read_u8(2)?, 0..=2);
+    let currents = [
+        read_current(bits, false)?,
+        read_current(bits, true)?,
+        read_current(bits, true)?,
+    ];
+    let wave_height = within(bits.read_u8(8)?, 0..=250).map(|h| h as f32 / 10.0);
+    let wave_period = within(bits.read_u8(6)?, 0..=60);
+    let wave_direction = within(bits.read_u32(9)? as u16, 0..=359);
+    let swell_height = within(bits.read_u8(8)?, 0..=250).map(|h| h as f32 / 10.0);
+    let swell_period = within(bits.read_u8(6)?, 0..=60);
+    let swell_direction = within(bits.read_u32(9)? as u16, 0..=359);
+    let sea_state = within(bits.read_u8(4)?, 0..=12);
+    let water_temperature = within(bits.read_i32(10)?, -100..=500).map(|t| t as f32 / 10.0);
+    let precipitation_type = within(bits.read_u8(3)?, 0..=6);
+    let salinity = within(bits.read_u32(9)?, 0..=500).map(|s| s as f32 / 10.0);
+    let ice = match bits.read_u8(2)? {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    };
+
+    Ok(ApplicationData::MetHydro(MetHydro {
+        longitude,
+        latitude,
+        position_accuracy,
+        day,
+        hour,
+        minute,
+        wind_speed,
+        wind_gust,
+        wind_direction,
+        wind_gust_direction,
+        air_temperature,
+        relative_humidity,
+        dew_point,
+        air_pressure,
+        air_pressure_tendency,
+        visibility,
+        visibility_greater_than: greater_than,
+        water_level,
+        water_level_trend,
+        currents,
+        wave_height,
+        wave_period,
+        wave_direction,
+        swell_height,
+        swell_period,
+        swell_direction,
+        sea_state,
+        water_temperature,
+        precipitation_type,
+        salinity,
+        ice,
+    }))
+}
+
+// IMO SN.1/Circ.289 area notice (DAC 1, FI 22)
+#[derive(Debug)]
+pub struct AreaNotice {
+    pub linkage_id: u16,
+    pub notice_type: u8, // IMO 289 notice description code, 127 is undefined
+    pub month: Option<u8>,
+    pub day: Option<u8>,
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub duration_minutes: Option<u32>,
+    pub subareas: Vec<SubArea>,
+}
+
+// Distances are in metres, angles in degrees
+#[derive(Debug, Serialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum SubArea {
+    Circle {
+        longitude: Option<f32>,
+        latitude: Option<f32>,
+        radius: u32,
+    },
+    Rectangle {
+        longitude: Option<f32>,
+        latitude: Option<f32>,
+        east: u32,
+        north: u32,
+        orientation: u16,
+    },
+    Sector {
+        longitude: Option<f32>,
+        latitude: Option<f32>,
+        radius: u32,
+        left_bound: u16,
+        right_bound: u16,
+    },
+    // Points relative to the previous sub-area's last point
+    Polyline {
+        points: Vec<(f32, u32)>,
+    },
+    Polygon {
+        points: Vec<(f32, u32)>,
+    },
+    Text {
+        text: String,
+    },
+    Reserved {
+        shape_id: u8,
+    },
+}
+
+const SUBAREA_BITS: usize = 87;
+
+fn read_subarea(bits: &mut BitReader) -> anyhow::Result<SubArea> {
+    let start = bits.position();
+    let shape = bits.read_u8(3)?;
+    let subarea = match shape {
+        0..=2 => {
+            let scale = 10u32.pow(bits.read_u32(2)?);
+            let (longitude, latitude) = read_position(bits)?;
+            bits.skip(3)?; // Precision
+            match shape {
+                0 => SubArea::Circle {
+                    longitude,
+                    latitude,
+                    radius: bits.read_u32(12)? * scale,
+                },
+                1 => SubArea::Rectangle {
+                    longitude,
+                    latitude,
+                    east: bits.read_u32(8)? * scale,
+                    north: bits.read_u32(8)? * scale,
+                    orientation: bits.read_u32(9)? as u16,
+                },
+                _ => SubArea::Sector {
+                    longitude,
+                    latitude,
+                    radius: bits.read_u32(12)? * scale,
+                    left_bound: bits.read_u32(9)? as u16,
+                    right_bound: bits.read_u32(9)? as u16,
+                },
+            }
+        }
+        3 | 4 => {
+            let scale = 10u32.pow(bits.read_u32(2)?);
+            let mut points = Vec::with_capacity(4);
+            for _ in 0..4 {
+                let angle = bits.read_u32(10)? as f32 / 2.0;
+                let distance = bits.read_u32(10)?;
+                // A zero distance marks the end of the point list
+                if distance > 0 {
+                    points.push((angle, distance * scale));
+                }
+            }
+            if shape == 3 {
+                SubArea::Polyline { points }
+            } else {
+                SubArea::Polygon { points }
+            }
+        }
+        5 => SubArea::Text {
+            text: bits.read_string(14)?,
+        },
+        shape_id => SubArea::Reserved { shape_id },
+    };
+    // Every shape takes the same number of bits, the rest is spare
+    bits.seek(start + SUBAREA_BITS)?;
+    Ok(subarea)
+}
+
+fn decode_area_notice(bits: &mut BitReader) -> anyhow::Result<ApplicationData> {
+    let linkage_id = bits.read_u32(10)? as u16;
+    let notice_type = bits.read_u8(7)?;
+    let month = within(bits.read_u8(4)?, 1..=12);
+    let day = within(bits.read_u8(5)?, 1..=31);
+    let hour = within(bits.read_u8(5)?, 0..=23);
+    let minute = within(bits.read_u8(6)?, 0..=59);
+    let duration_minutes = within(bits.read_u32(18)?, 0..=262_142);
+
+    let mut subareas = Vec::new();
+    while bits.remaining() >= SUBAREA_BITS {
+        subareas.push(read_subarea(bits)?);
+    }
+
+    Ok(ApplicationData::AreaNotice(AreaNotice {
+        linkage_id,
+        notice_type,
+        month,
+        day,
+        hour,
+        minute,
+        duration_minutes,
+        subareas,
+    }))
+}
+
+// UK/ROI lighthouse authorities' AtoN monitoring data (DAC 235 or 250, FI 10)
+#[derive(Debug)]
+pub struct AtonMonitoring {
+    pub analogue_internal: f32, // Supply voltage, volts
+    pub analogue_external1: f32,
+    pub analogue_external2: f32,
+    pub status: MonitoredStatus,
+    pub digital_inputs: u8, // External digital inputs, one bit each
+    pub off_position: bool,
+}
+
+fn decode_aton_monitoring(bits: &mut BitReader) -> anyhow::Result<ApplicationData> {
+    let analogue_internal = bits.read_u32(10)? as f32 * 0.05;
+    let analogue_external1 = bits.read_u32(10)? as f32 * 0.05;
+    let analogue_external2 = bits.read_u32(10)? as f32 * 0.05;
+    // RACON (2 bits), light (2 bits) and health (1 bit), as on Message 21 page 7
+    let status = MonitoredStatus::read(bits)?;
+    let digital_inputs = bits.read_u8(8)?;
+    let off_position = bits.read_bool()?;
+
+    Ok(ApplicationData::AtonMonitoring(AtonMonitoring {
+        analogue_internal,
+        analogue_external1,
+        analogue_external2,
+        status,
+        digital_inputs,
+        off_position,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ais::msg21::{GeneralHealth, LightStatus, RaconStatus};
+
+    fn binary(payload: &str, fill_bits: u8) -> BinaryMessage {
+        let unarmored = ais::messages::unarmor(payload.as_bytes(), fill_bits as usize).unwrap();
+        let message = ais::messages::parse(&unarmored).unwrap();
+        BinaryMessage::new(message, payload, fill_bits).unwrap()
+    }
+
+    fn assert_near(value: Option<f32>, expected: f32) {
+        let value = value.expect("a value");
+        assert!((value - expected).abs() < 1e-3, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn met_hydro() {
+        // !AIVDM,1,1,,A,8@2R5Ph0GhEa?1bGBviEOwvlFR06EuOwgqriwnSwe7wvlOwwsAwwnSGmwvwt,0*64
+        let message = binary(
+            "8@2R5Ph0GhEa?1bGBviEOwvlFR06EuOwgqriwnSwe7wvlOwwsAwwnSGmwvwt",
+            0,
+        );
+        assert_eq!((message.mmsi, message.dac, message.fi), (2655619, 1, 31));
+        let Some(ApplicationData::MetHydro(mh)) = message.application else {
+            panic!("not decoded: {:?}", message.application);
+        };
+        assert_near(mh.longitude, 11.820667);
+        assert_near(mh.latitude, 58.088917);
+        assert!(mh.position_accuracy);
+        assert_eq!((mh.day, mh.hour, mh.minute), (Some(22), Some(5), Some(21)));
+        assert_near(mh.water_level, -0.18);
+        assert_eq!(mh.water_level_trend, Some(0));
+        // Everything else is sent as "not available"
+        assert_eq!(mh.wind_speed, None);
+        assert_eq!(mh.wind_direction, None);
+        assert_eq!(mh.air_temperature, None);
+        assert_eq!(mh.air_pressure, None);
+        assert_eq!(mh.currents[0].speed, None);
+        assert_eq!(mh.currents[1].depth, None);
+        assert_eq!(mh.sea_state, None);
+        assert_eq!(mh.salinity, None);
+        assert_eq!(mh.ice, None);
+    }
+
+    #[test]
+    fn area_notice() {
+        // !AIVDM,1,1,,A,81mg=5@0EP:0>H0007P>0<D1<qp400000,0*1D
+        let message = binary("81mg=5@0EP:0>H0007P>0<D1<qp400000", 0);
+        assert_eq!((message.mmsi, message.dac, message.fi), (123456789, 1, 22));
+        let Some(ApplicationData::AreaNotice(notice)) = message.application else {
+            panic!("not decoded: {:?}", message.application);
+        };
+        assert_eq!(notice.linkage_id, 10);
+        assert_eq!(notice.notice_type, 0);
+        assert_eq!(
+            (notice.month, notice.day, notice.hour, notice.minute),
+            (Some(7), Some(6), Some(0), Some(0))
+        );
+        assert_eq!(notice.duration_minutes, Some(60));
+        let [
+            SubArea::Circle {
+                longitude,
+                latitude,
+                radius,
+            },
+        ] = notice.subareas.as_slice()
+        else {
+            panic!("expected one circle: {:?}", notice.subareas);
+        };
+        assert_near(*longitude, -69.8);
+        assert_near(*latitude, 42.0);
+        assert_eq!(*radius, 0);
+    }
+
+    #[test]
+    fn aton_monitoring() {
+        // !AIVDM,1,1,,A,6>jCKIkfJjOt>db;q700@20,2*16
+        let message = binary("6>jCKIkfJjOt>db;q700@20", 2);
+        assert_eq!(
+            (message.mmsi, message.dac, message.fi),
+            (992271207, 235, 10)
+        );
+        let Some(ApplicationData::AtonMonitoring(aton)) = message.application else {
+            panic!("not decoded: {:?}", message.application);
+        };
+        assert_near(Some(aton.analogue_internal), 27.95);
+        assert_near(Some(aton.analogue_external1), 29.15);
+        assert_near(Some(aton.analogue_external2), 0.0);
+        assert_eq!(
+            aton.status,
+            MonitoredStatus {
+                racon: RaconStatus::NotFitted,
+                light: LightStatus::On,
+                health: GeneralHealth::Good,
+            }
+        );
+        assert_eq!(aton.digital_inputs, 1);
+        assert!(!aton.off_position);
+    }
+
+    #[test]
+    fn aton_monitoring_roi_uses_the_uk_layout() {
+        let mut bits = BitReader::new("6>jCKIkfJjOt>db;q700@20", 2).unwrap();
+        bits.seek(88).unwrap();
+        let Some(Ok(ApplicationData::AtonMonitoring(aton))) = decode(250, 10, &mut bits) else {
+            panic!("DAC 250 FI 10 not decoded");
+        };
+        assert_eq!(aton.status.light, LightStatus::On);
+        assert_eq!(
+            application_name(250, 10),
+            Some("AtoN monitoring data (ROI)")
+        );
+        assert!(decode(250, 11, &mut bits).is_none());
+    }
+}
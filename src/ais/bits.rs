@@ -0,0 +1,216 @@
+use anyhow::ensure;
+
+// Value of one armored payload character: '0'..='W' map to 0..=39 and '`'..='w' to 40..=63
+pub fn sixbit(c: u8) -> Option<u8> {
+    match c {
+        48..=87 => Some(c - 48),
+        96..=119 => Some(c - 56),
+        _ => None,
+    }
+}
+
+// Reads fields straight out of an armored AIS payload without unpacking it first.
+// Fields are read MSB first from the current position, which moves past them.
+#[derive(Clone, Debug)]
+pub struct BitReader<'a> {
+    payload: &'a [u8],
+    len: usize, // Payload bits minus the fill bits
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(payload: &'a str, fill_bits: u8) -> anyhow::Result<Self> {
+        let payload = payload.as_bytes();
+        ensure!(fill_bits <= 5, "Invalid fill bit count {}", fill_bits);
+        if let Some(c) = payload.iter().find(|c| sixbit(**c).is_none()) {
+            anyhow::bail!("Invalid payload character {:?}", *c as char);
+        }
+        let len = (payload.len() * 6).saturating_sub(fill_bits as usize);
+        Ok(Self {
+            payload,
+            len,
+            pos: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[allow(dead_code)] // kept alongside len()
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+
+    pub fn seek(&mut self, pos: usize) -> anyhow::Result<()> {
+        ensure!(
+            pos <= self.len,
+            "Seek to bit {} past end ({})",
+            pos,
+            self.len
+        );
+        self.pos = pos;
+        Ok(())
+    }
+
+    pub fn skip(&mut self, bits: usize) -> anyhow::Result<()> {
+        self.seek(self.pos + bits)
+    }
+
+    // Unsigned field of up to 64 bits
+    pub fn read_u64(&mut self, bits: usize) -> anyhow::Result<u64> {
+        ensure!(bits <= 64, "Field of {} bits is wider than 64", bits);
+        ensure!(
+            bits <= self.remaining(),
+            "Field of {} bits at {} runs past the end of the payload ({} bits)",
+            bits,
+            self.pos,
+            self.len
+        );
+
+        let mut value = 0u64;
+        let mut left = bits;
+        while left > 0 {
+            let offset = self.pos % 6;
+            let take = (6 - offset).min(left);
+            let c = sixbit(self.payload[self.pos / 6]).unwrap_or(0);
+            let chunk = (c >> (6 - offset - take)) & ((1 << take) - 1);
+            value = (value << take) | chunk as u64;
+            self.pos += take;
+            left -= take;
+        }
+        Ok(value)
+    }
+
+    pub fn read_u32(&mut self, bits: usize) -> anyhow::Result<u32> {
+        ensure!(bits <= 32, "Field of {} bits is wider than 32", bits);
+        Ok(self.read_u64(bits)? as u32)
+    }
+
+    pub fn read_u8(&mut self, bits: usize) -> anyhow::Result<u8> {
+        ensure!(bits <= 8, "Field of {} bits is wider than 8", bits);
+        Ok(self.read_u64(bits)? as u8)
+    }
+
+    pub fn read_bool(&mut self) -> anyhow::Result<bool> {
+        Ok(self.read_u64(1)? == 1)
+    }
+
+    // Two's complement signed field of up to 64 bits
+    pub fn read_i64(&mut self, bits: usize) -> anyhow::Result<i64> {
+        ensure!(bits > 0, "Signed field needs at least one bit");
+        let value = self.read_u64(bits)?;
+        let shift = 64 - bits as u32;
+        Ok(((value << shift) as i64) >> shift)
+    }
+
+    pub fn read_i32(&mut self, bits: usize) -> anyhow::Result<i32> {
+        ensure!(bits <= 32, "Field of {} bits is wider than 32", bits);
+        Ok(self.read_i64(bits)? as i32)
+    }
+
+    // Six-bit ASCII text of `chars` characters, with '@' padding and trailing spaces removed
+    pub fn read_string(&mut self, chars: usize) -> anyhow::Result<String> {
+        let mut text = String::with_capacity(chars);
+        for _ in 0..chars {
+            let c = self.read_u8(6)?;
+            // 0..=31 are '@'..'_', 32..=63 are ' '..'?'
+            text.push(if c < 32 { (c + 64) as char } else { c as char });
+        }
+        let end = text.find('@').unwrap_or(text.len());
+        text.truncate(end);
+        text.truncate(text.trim_end().len());
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Position report:
+    // !AIVDM,1,1,,B,15M67FC000G?ufbE`FepT@3n00Sa,0*5C
+    const TYPE_1: &str = "15M67FC000G?ufbE`FepT@3n00Sa";
+    // Static data report part A, 27 characters less 2 fill bits:
+    // !AIVDM,1,1,,B,H42O55i18tMET00000000000000,2*6E
+    const TYPE_24: &str = "H42O55i18tMET00000000000000";
+
+    #[test]
+    fn sixbit_skips_the_gap_between_w_and_backtick() {
+        assert_eq!(sixbit(b'0'), Some(0));
+        assert_eq!(sixbit(b'W'), Some(39));
+        assert_eq!(sixbit(b'`'), Some(40));
+        assert_eq!(sixbit(b'w'), Some(63));
+        for c in 88..=95 {
+            assert_eq!(sixbit(c), None, "{:?}", c as char);
+        }
+        assert_eq!(sixbit(b'/'), None);
+        assert_eq!(sixbit(b'x'), None);
+    }
+
+    #[test]
+    fn rejects_characters_outside_the_armor() {
+        for c in 88..=95u8 {
+            let payload = format!("15M{}", c as char);
+            assert!(BitReader::new(&payload, 0).is_err(), "{:?}", c as char);
+        }
+        assert!(BitReader::new("15M,", 0).is_err());
+    }
+
+    #[test]
+    fn reads_a_position_report() {
+        let mut bits = BitReader::new(TYPE_1, 0).unwrap();
+        assert_eq!(bits.len(), 168);
+        assert_eq!(bits.read_u8(6).unwrap(), 1);
+        assert_eq!(bits.read_u8(2).unwrap(), 0);
+        assert_eq!(bits.read_u32(30).unwrap(), 366053209);
+        bits.seek(61).unwrap();
+        let longitude = bits.read_i32(28).unwrap() as f64 / 600_000.0;
+        let latitude = bits.read_i32(27).unwrap() as f64 / 600_000.0;
+        assert!((longitude - -122.341618).abs() < 1e-6);
+        assert!((latitude - 37.802118).abs() < 1e-6);
+    }
+
+    #[test]
+    fn backtick_to_w_carry_their_bits() {
+        // '`' is 101000 and 'w' is 111111
+        let mut bits = BitReader::new("`w", 0).unwrap();
+        assert_eq!(bits.read_u8(6).unwrap(), 40);
+        assert_eq!(bits.read_u8(6).unwrap(), 63);
+        // Bits at the character boundary read across both
+        bits.seek(4).unwrap();
+        assert_eq!(bits.read_u8(4).unwrap(), 0b0011);
+    }
+
+    #[test]
+    fn fill_bits_are_not_read() {
+        let mut bits = BitReader::new(TYPE_24, 2).unwrap();
+        assert_eq!(bits.len(), 160);
+        assert_eq!(bits.read_u8(6).unwrap(), 24);
+        bits.skip(2).unwrap();
+        assert_eq!(bits.read_u32(30).unwrap(), 271041815);
+        assert_eq!(bits.read_u8(2).unwrap(), 0); // Part A
+        assert_eq!(bits.read_string(20).unwrap(), "PROGUY");
+        assert_eq!(bits.remaining(), 0);
+        assert!(bits.read_bool().is_err());
+
+        assert!(BitReader::new(TYPE_24, 6).is_err());
+    }
+
+    #[test]
+    fn signed_fields_are_twos_complement() {
+        // 111111 111110
+        let mut bits = BitReader::new("wv", 0).unwrap();
+        assert_eq!(bits.read_i32(12).unwrap(), -2);
+        bits.seek(0).unwrap();
+        assert_eq!(bits.read_i32(6).unwrap(), -1);
+    }
+}
@@ -0,0 +1,424 @@
+use super::tag_block::TagBlock;
+use anyhow::{Context, ensure};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// A sentence or TAG block whose checksum doesn't match its contents
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub what: &'static str,
+    pub expected: u8,
+    pub actual: u8,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} checksum mismatch (expected {:02X}, got {:02X})",
+            self.what, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+// How a message was framed on the wire, so it can be re-armored the same way
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Framing {
+    pub talker: String, // e.g. AI, AB (base station), BS
+    pub own_ship: bool, // VDO, the receiver's own transmissions, rather than VDM
+    pub channel: Option<char>,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            talker: "AI".into(),
+            own_ship: false,
+            channel: None,
+        }
+    }
+}
+
+// The fields of one !xxVDM/!xxVDO sentence, checksum verified but payload not decoded
+#[derive(Debug)]
+pub struct Sentence<'a> {
+    pub talker: &'a str,
+    pub own_ship: bool,
+    pub total: u8,
+    pub number: u8,
+    pub message_id: Option<u8>,
+    pub channel: Option<char>,
+    pub payload: &'a str,
+    pub fill_bits: u8,
+}
+
+impl<'a> Sentence<'a> {
+    pub fn parse(line: &'a str) -> anyhow::Result<Self> {
+        let body = line
+            .strip_prefix('!')
+            .or_else(|| line.strip_prefix('$'))
+            .context("Sentence does not start with '!'")?;
+        let (data, sum) = body.rsplit_once('*').context("Sentence without checksum")?;
+        let sum = u8::from_str_radix(sum, 16).context("Invalid sentence checksum")?;
+        let actual = data.bytes().fold(0, |sum, b| sum ^ b);
+        if actual != sum {
+            return Err(ChecksumMismatch {
+                what: "Sentence",
+                expected: sum,
+                actual,
+            }
+            .into());
+        }
+
+        let fields: Vec<&str> = data.split(',').collect();
+        ensure!(fields.len() == 7, "Expected 7 fields, got {}", fields.len());
+        let (talker, formatter) = fields[0]
+            .split_at_checked(fields[0].len().saturating_sub(3))
+            .context("Invalid sentence address")?;
+        ensure!(
+            formatter == "VDM" || formatter == "VDO",
+            "Not an AIS sentence: {}",
+            fields[0]
+        );
+        let total: u8 = fields[1].parse().context("Invalid fragment count")?;
+        let number: u8 = fields[2].parse().context("Invalid fragment number")?;
+        ensure!(
+            (1..=total).contains(&number),
+            "Fragment {} of {}",
+            number,
+            total
+        );
+        let message_id = match fields[3] {
+            "" => None,
+            id => Some(id.parse().context("Invalid sequential message ID")?),
+        };
+        let fill_bits: u8 = fields[6].parse().context("Invalid fill bit count")?;
+        ensure!(fill_bits <= 5, "Invalid fill bit count {}", fill_bits);
+
+        Ok(Self {
+            talker,
+            own_ship: formatter == "VDO",
+            total,
+            number,
+            message_id,
+            channel: fields[4].chars().next(),
+            payload: fields[5],
+            fill_bits,
+        })
+    }
+
+    pub fn framing(&self) -> Framing {
+        Framing {
+            talker: self.talker.to_string(),
+            own_ship: self.own_ship,
+            channel: self.channel,
+        }
+    }
+}
+
+// A whole message, either a single sentence or the joined payloads of its fragments
+#[derive(Debug)]
+pub struct Assembled {
+    pub payload: String,
+    pub fill_bits: u8,
+    pub framing: Framing, // Of the first fragment
+    pub tag: Option<TagBlock>,
+}
+
+#[derive(Default)]
+pub struct FragmentStats {
+    completed: AtomicU64,
+    orphaned: AtomicU64,
+    expired: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct FragmentStatsSnapshot {
+    pub completed: u64, // Multi-sentence messages put back together
+    pub orphaned: u64, // Fragments thrown away because their neighbours were missing or out of order
+    pub expired: u64,  // Partial messages dropped after the fragment timeout
+}
+
+impl FragmentStats {
+    pub fn snapshot(&self) -> FragmentStatsSnapshot {
+        FragmentStatsSnapshot {
+            completed: self.completed.load(Ordering::Relaxed),
+            orphaned: self.orphaned.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Partial {
+    total: u8,
+    received: u8,
+    payload: String,
+    framing: Framing,
+    tag: Option<TagBlock>, // From the first fragment
+    started: Instant,
+}
+
+// Which receiver's stream a fragment belongs to, from its TAG block. An
+// aggregator feed interleaves many receivers that reuse the same message IDs.
+// The g: group is on every line of a message, s: often only on the first.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Stream {
+    Group(u32),
+    Source(String),
+    Connection, // No TAG block: the connection is the only stream
+}
+
+impl Stream {
+    fn of(tag: Option<&TagBlock>) -> Self {
+        match tag {
+            Some(TagBlock {
+                group_id: Some(group),
+                ..
+            }) => Stream::Group(*group),
+            Some(TagBlock {
+                source: Some(source),
+                ..
+            }) => Stream::Source(source.clone()),
+            _ => Stream::Connection,
+        }
+    }
+}
+
+// Joins multi-sentence messages for one connection. Fragments are matched on
+// TAG group or source, sequential message ID and radio channel, so interleaved
+// messages don't mix.
+pub struct FragmentAssembler {
+    pending: HashMap<(Stream, Option<u8>, Option<char>), Partial>,
+    timeout: Duration,
+    stats: Arc<FragmentStats>,
+}
+
+impl FragmentAssembler {
+    pub fn new(timeout: Duration, stats: Arc<FragmentStats>) -> Self {
+        Self {
+            pending: HashMap::new(),
+            timeout,
+            stats,
+        }
+    }
+
+    // Returns the message once its last fragment arrives
+    pub fn push(
+        &mut self,
+        sentence: &Sentence,
+        tag: Option<TagBlock>,
+        now: Instant,
+    ) -> Option<Assembled> {
+        self.expire(now);
+
+        if sentence.total == 1 {
+            return Some(Assembled {
+                payload: sentence.payload.to_string(),
+                fill_bits: sentence.fill_bits,
+                framing: sentence.framing(),
+                tag,
+            });
+        }
+
+        let key = (
+            Stream::of(tag.as_ref()),
+            sentence.message_id,
+            sentence.channel,
+        );
+        if sentence.number == 1 {
+            let partial = Partial {
+                total: sentence.total,
+                received: 1,
+                payload: sentence.payload.to_string(),
+                framing: sentence.framing(),
+                tag,
+                started: now,
+            };
+            // A new first fragment means the previous message under this key never finished
+            if let Some(stale) = self.pending.insert(key, partial) {
+                self.orphan(stale.received as u64);
+            }
+            return None;
+        }
+
+        let Some(partial) = self.pending.get_mut(&key) else {
+            self.orphan(1);
+            return None;
+        };
+        if partial.total != sentence.total || partial.received + 1 != sentence.number {
+            let received = partial.received as u64;
+            self.pending.remove(&key);
+            self.orphan(received + 1);
+            return None;
+        }
+
+        partial.received += 1;
+        partial.payload.push_str(sentence.payload);
+        if partial.received < partial.total {
+            return None;
+        }
+
+        let partial = self.pending.remove(&key)?;
+        self.stats.completed.fetch_add(1, Ordering::Relaxed);
+        let tag = match (tag, partial.tag) {
+            (Some(tag), Some(first)) => Some(tag.merge(&first)),
+            (tag, first) => tag.or(first),
+        };
+        Some(Assembled {
+            payload: partial.payload,
+            fill_bits: sentence.fill_bits,
+            framing: partial.framing,
+            tag,
+        })
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, partial| now.duration_since(partial.started) < timeout);
+        let expired = (before - self.pending.len()) as u64;
+        if expired > 0 {
+            self.stats.expired.fetch_add(expired, Ordering::Relaxed);
+        }
+    }
+
+    fn orphan(&self, fragments: u64) {
+        self.stats.orphaned.fetch_add(fragments, Ordering::Relaxed);
+    }
+}
+
+// Longest payload put in one sentence, keeping it under the 82 character NMEA limit
+const MAX_SENTENCE_PAYLOAD: usize = 60;
+
+// The inverse of assembly: armors a payload back into sentences framed as it was
+// received. Messages needing several sentences get `message_id` (0-9) as their
+// sequential message ID.
+pub fn to_sentences(
+    payload: &str,
+    fill_bits: u8,
+    framing: &Framing,
+    message_id: u8,
+) -> Vec<String> {
+    let chunks: Vec<&str> = payload
+        .as_bytes()
+        .chunks(MAX_SENTENCE_PAYLOAD)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect();
+    let total = chunks.len();
+    let formatter = if framing.own_ship { "VDO" } else { "VDM" };
+    let channel = framing.channel.map(String::from).unwrap_or_default();
+    let id = if total > 1 {
+        message_id.to_string()
+    } else {
+        String::new()
+    };
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let fill = if i + 1 == total { fill_bits } else { 0 };
+            let data = format!(
+                "{}{},{},{},{},{},{},{}",
+                framing.talker,
+                formatter,
+                total,
+                i + 1,
+                id,
+                channel,
+                chunk,
+                fill
+            );
+            let sum = data.bytes().fold(0, |sum, b| sum ^ b);
+            format!("!{}*{:02X}", data, sum)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(source: Option<&str>, group_id: Option<u32>) -> Option<TagBlock> {
+        Some(TagBlock {
+            source: source.map(str::to_string),
+            timestamp: None,
+            group_id,
+        })
+    }
+
+    #[test]
+    fn sources_reusing_a_message_id_are_kept_apart() {
+        let stats = Arc::new(FragmentStats::default());
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(5), stats.clone());
+        let a = to_sentences(&"A".repeat(70), 2, &Framing::default(), 3);
+        let b = to_sentences(&"B".repeat(70), 2, &Framing::default(), 3);
+        let now = Instant::now();
+        let mut push = |line: &str, source| {
+            let sentence = Sentence::parse(line).unwrap();
+            assembler.push(&sentence, tag(Some(source), None), now)
+        };
+
+        assert!(push(&a[0], "r1").is_none());
+        assert!(push(&b[0], "r2").is_none());
+        let first = push(&a[1], "r1").unwrap();
+        let second = push(&b[1], "r2").unwrap();
+        assert_eq!(first.payload, "A".repeat(70));
+        assert_eq!(second.payload, "B".repeat(70));
+        assert_eq!(stats.snapshot().orphaned, 0);
+    }
+
+    #[test]
+    fn group_matches_lines_without_a_source() {
+        let stats = Arc::new(FragmentStats::default());
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(5), stats.clone());
+        let lines = to_sentences(&"C".repeat(70), 0, &Framing::default(), 1);
+        let now = Instant::now();
+        let first = Sentence::parse(&lines[0]).unwrap();
+        let second = Sentence::parse(&lines[1]).unwrap();
+
+        assert!(
+            assembler
+                .push(&first, tag(Some("r1"), Some(42)), now)
+                .is_none()
+        );
+        let assembled = assembler.push(&second, tag(None, Some(42)), now).unwrap();
+        assert_eq!(assembled.payload, "C".repeat(70));
+        assert_eq!(assembled.tag.unwrap().source.as_deref(), Some("r1"));
+    }
+
+    #[test]
+    fn rearmors_as_received() {
+        let stats = Arc::new(FragmentStats::default());
+        let mut assembler = FragmentAssembler::new(Duration::from_secs(5), stats);
+        let now = Instant::now();
+        let roundtrip = |assembler: &mut FragmentAssembler, lines: &[&str]| {
+            let mut assembled = None;
+            for line in lines {
+                assembled = assembler.push(&Sentence::parse(line).unwrap(), None, now);
+            }
+            let assembled = assembled.unwrap();
+            to_sentences(
+                &assembled.payload,
+                assembled.fill_bits,
+                &assembled.framing,
+                1,
+            )
+        };
+
+        let own_ship = ["!BSVDO,1,1,,B,13u?etPv2;0n:dDPwUM1U1Cb069D,0*3C"];
+        assert_eq!(roundtrip(&mut assembler, &own_ship), own_ship);
+
+        let static_voyage = [
+            "!AIVDM,2,1,1,A,55?MbV02;H;s<HtKR20EHE:0@T4@Dn2222222216L961O5Gf0NSQEp6ClRp8,0*1C",
+            "!AIVDM,2,2,1,A,88888888880,2*25",
+        ];
+        assert_eq!(roundtrip(&mut assembler, &static_voyage), static_voyage);
+    }
+}
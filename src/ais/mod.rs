@@ -1,2 +1,7 @@
+pub mod binary;
+pub mod bits;
 pub mod decoder;
-mod msg21;
+pub mod fragments;
+pub mod msg21;
+pub mod tag_block;
+pub mod validation;
@@ -1,13 +1,20 @@
-#[derive(Debug)]
+use super::bits::BitReader;
+
+// The regional status byte follows the off-position flag, at bit 260
+const STATUS_OFFSET: usize = 260;
+// Message 21 without the name extension
+const MIN_BITS: usize = 272;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RaconStatus {
     NotFitted,
     NotMonitored,
     Operational,
-    Test,
+    Error, // 0b11 is a RACON error in IALA A-126, not a test state
     Unknown,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LightStatus {
     NoLightOrNotMonitored,
     On,
@@ -16,9 +23,154 @@ pub enum LightStatus {
     Unknown,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum GeneralHealth {
     Good,
     Alarm,
-    Unknown,
+}
+
+// RACON, light and health bits as laid out on page 7 of IALA A-126
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MonitoredStatus {
+    pub racon: RaconStatus,
+    pub light: LightStatus,
+    pub health: GeneralHealth,
+}
+
+impl MonitoredStatus {
+    // The five status bits following the page ID
+    pub fn read(bits: &mut BitReader) -> anyhow::Result<Self> {
+        let racon_bits = bits.read_u8(2)?;
+        let light_bits = bits.read_u8(2)?;
+        let health_bit = bits.read_bool()?;
+
+        Ok(Self {
+            racon: match racon_bits {
+                0b00 => RaconStatus::NotFitted,
+                0b01 => RaconStatus::NotMonitored,
+                0b10 => RaconStatus::Operational,
+                0b11 => RaconStatus::Error,
+                _ => RaconStatus::Unknown,
+            },
+            light: match light_bits {
+                0b00 => LightStatus::NoLightOrNotMonitored,
+                0b01 => LightStatus::On,
+                0b10 => LightStatus::Off,
+                0b11 => LightStatus::FailOrReducedRange,
+                _ => LightStatus::Unknown,
+            },
+            health: match health_bit {
+                false => GeneralHealth::Good,
+                true => GeneralHealth::Alarm,
+            },
+        })
+    }
+}
+
+// The Message 21 regional status byte: a 3-bit page ID followed by 5 status bits.
+// Only page 7 is decoded. The other pages are not supported: their layouts are
+// left to each competent authority and we have none we could rely on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtonStatusPage {
+    Fixed(MonitoredStatus), // Page 0b111
+    // Pages 0-6, not decoded. The bits are kept as they are (also in
+    // ais_aton_reports.regional_reserved) and carry no RACON, light or health.
+    Unsupported { page_id: u8, bits: u8 },
+}
+
+impl AtonStatusPage {
+    // Reads the status byte of an armored Message 21 payload
+    pub fn from_payload(payload: &str, fill_bits: u8) -> anyhow::Result<Self> {
+        let mut bits = BitReader::new(payload, fill_bits)?;
+        anyhow::ensure!(
+            bits.len() >= MIN_BITS,
+            "Message 21 too short ({} bits, expected >={})",
+            bits.len(),
+            MIN_BITS
+        );
+        bits.seek(STATUS_OFFSET)?;
+        Self::read(&mut bits)
+    }
+
+    pub fn read(bits: &mut BitReader) -> anyhow::Result<Self> {
+        let page_id = bits.read_u8(3)?;
+        Ok(match page_id {
+            0b111 => Self::Fixed(MonitoredStatus::read(bits)?),
+            _ => Self::Unsupported {
+                page_id,
+                bits: bits.read_u8(5)?,
+            },
+        })
+    }
+
+    pub fn page_id(&self) -> u8 {
+        match self {
+            Self::Fixed(_) => 0b111,
+            Self::Unsupported { page_id, .. } => *page_id,
+        }
+    }
+
+    // RACON, light and health status, for the page that carries them
+    pub fn monitored(&self) -> Option<MonitoredStatus> {
+        match self {
+            Self::Fixed(status) => Some(*status),
+            Self::Unsupported { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A Message 21 from MMSI 993692028 with the status byte and
+    // off-position flag set to each case
+    const PAGE_7: &str = "E>kb9O9aS@7PUh10dh19@;0Tah2cWrfP:l?M`00003vfH00"; // 111 00 11 0
+    const PAGE_5: &str = "E>kb9O9aS@7PUh10dh19@;0Tah2cWrfP:l?M`00003vs400"; // 101 10 00 1
+    const PAGE_1: &str = "E>kb9O9aS@7PUh10dh19@;0Tah2cWrfP:l?M`00003vR`00"; // 001 01010
+
+    #[test]
+    fn fixed_page_carries_light_failure() {
+        let page = AtonStatusPage::from_payload(PAGE_7, 0).unwrap();
+        assert_eq!(
+            page,
+            AtonStatusPage::Fixed(MonitoredStatus {
+                racon: RaconStatus::NotFitted,
+                light: LightStatus::FailOrReducedRange,
+                health: GeneralHealth::Good,
+            })
+        );
+        assert_eq!(page.page_id(), 7);
+    }
+
+    #[test]
+    fn page_5_is_not_decoded() {
+        let page = AtonStatusPage::from_payload(PAGE_5, 0).unwrap();
+        assert_eq!(
+            page,
+            AtonStatusPage::Unsupported {
+                page_id: 5,
+                bits: 0b10001
+            }
+        );
+        assert_eq!(page.monitored(), None);
+    }
+
+    #[test]
+    fn other_pages_keep_their_bits() {
+        let page = AtonStatusPage::from_payload(PAGE_1, 0).unwrap();
+        assert_eq!(
+            page,
+            AtonStatusPage::Unsupported {
+                page_id: 1,
+                bits: 0b01010
+            }
+        );
+        assert_eq!(page.monitored(), None);
+    }
+
+    #[test]
+    fn short_payload_is_an_error() {
+        assert!(AtonStatusPage::from_payload(&PAGE_7[..40], 0).is_err());
+    }
 }
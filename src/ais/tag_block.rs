@@ -0,0 +1,168 @@
+use super::fragments::ChecksumMismatch;
+use anyhow::{Context, bail};
+use chrono::{DateTime, Utc};
+
+// NMEA 4.x TAG block in front of a sentence, e.g. \s:r003669945,c:1241544035*79\!AIVDM,...
+// Only the fields we store are kept; d:, n:, r: and t: are checked for syntax and dropped.
+#[derive(Clone, Debug, Default)]
+pub struct TagBlock {
+    pub source: Option<String>, // s: the station that received the sentence
+    pub timestamp: Option<DateTime<Utc>>, // c: receiver time
+    pub group_id: Option<u32>,  // g: ties the lines of a multi-sentence message together
+}
+
+impl TagBlock {
+    // Lines after the first of a group usually carry only the g: field; they take
+    // source and time from the first line
+    pub fn merge(mut self, first: &TagBlock) -> Self {
+        if self.group_id.is_none() || self.group_id == first.group_id {
+            self.source = self.source.or_else(|| first.source.clone());
+            self.timestamp = self.timestamp.or(first.timestamp);
+        }
+        self
+    }
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, b| sum ^ b)
+}
+
+// Receivers send either seconds or milliseconds since the epoch
+fn parse_timestamp(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    let value: i64 = value.parse().context("Invalid TAG timestamp")?;
+    let timestamp = if value > 100_000_000_000 {
+        DateTime::from_timestamp_millis(value)
+    } else {
+        DateTime::from_timestamp(value, 0)
+    };
+    timestamp.context("TAG timestamp out of range")
+}
+
+// sentence-total-id, e.g. 1-2-1234
+fn parse_group(value: &str) -> anyhow::Result<u32> {
+    let parts: Vec<_> = value.split('-').map(str::parse::<u32>).collect();
+    match parts.as_slice() {
+        [Ok(_), Ok(_), Ok(id)] => Ok(*id),
+        _ => bail!("Invalid TAG group {:?}", value),
+    }
+}
+
+fn parse_fields(fields: &str) -> anyhow::Result<TagBlock> {
+    let mut tag = TagBlock::default();
+    for field in fields.split(',') {
+        let (code, value) = field
+            .split_once(':')
+            .with_context(|| format!("Invalid TAG field {:?}", field))?;
+        match code {
+            "s" => tag.source = Some(value.to_string()),
+            "c" => tag.timestamp = Some(parse_timestamp(value)?),
+            "g" => tag.group_id = Some(parse_group(value)?),
+            "n" | "r" => {
+                value
+                    .parse::<u32>()
+                    .with_context(|| format!("Invalid TAG field {:?}", field))?;
+            }
+            _ => {} // d:, t: and unknown fields are free text
+        }
+    }
+    Ok(tag)
+}
+
+// Splits a line into its TAG block (if any) and the sentence behind it. A TAG
+// block with a bad checksum rejects the whole line.
+pub fn split(line: &str) -> anyhow::Result<(Option<TagBlock>, &str)> {
+    let Some(rest) = line.strip_prefix('\\') else {
+        return Ok((None, line));
+    };
+    let (block, sentence) = rest.split_once('\\').context("Unterminated TAG block")?;
+    let (fields, sum) = block
+        .rsplit_once('*')
+        .context("TAG block without checksum")?;
+    let sum = u8::from_str_radix(sum, 16).context("Invalid TAG block checksum")?;
+    let actual = checksum(fields);
+    if actual != sum {
+        return Err(ChecksumMismatch {
+            what: "TAG block",
+            expected: sum,
+            actual,
+        }
+        .into());
+    }
+    Ok((Some(parse_fields(fields)?), sentence))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENTENCE: &str = "!AIVDM,1,1,,A,15MgK45P3@G?fl0E`JbR0OwT0@MS,0*4E";
+
+    #[test]
+    fn line_without_tag_block_is_passed_through() {
+        let (tag, sentence) = split(SENTENCE).unwrap();
+        assert!(tag.is_none());
+        assert_eq!(sentence, SENTENCE);
+    }
+
+    #[test]
+    fn source_and_time_in_seconds() {
+        let line = format!("\\s:rX,c:1700006400*12\\{}", SENTENCE);
+        let (tag, sentence) = split(&line).unwrap();
+        let tag = tag.unwrap();
+        assert_eq!(sentence, SENTENCE);
+        assert_eq!(tag.source.as_deref(), Some("rX"));
+        assert_eq!(tag.timestamp, DateTime::from_timestamp(1_700_006_400, 0));
+        assert_eq!(tag.group_id, None);
+    }
+
+    #[test]
+    fn time_in_milliseconds() {
+        let tag = parse_fields("c:1700006400123").unwrap();
+        assert_eq!(
+            tag.timestamp,
+            DateTime::from_timestamp_millis(1_700_006_400_123)
+        );
+    }
+
+    #[test]
+    fn bad_checksum_rejects_the_line() {
+        let line = format!("\\s:rX,c:1700006400*13\\{}", SENTENCE);
+        let error = split(&line).unwrap_err();
+        let mismatch = error.downcast_ref::<ChecksumMismatch>().unwrap();
+        assert_eq!((mismatch.expected, mismatch.actual), (0x13, 0x12));
+    }
+
+    #[test]
+    fn missing_checksum_rejects_the_line() {
+        let line = format!("\\s:rX,c:1700006400\\{}", SENTENCE);
+        assert!(split(&line).is_err());
+    }
+
+    #[test]
+    fn later_lines_of_a_group_take_source_and_time_from_the_first() {
+        let (first, _) = split(&format!(
+            "\\g:1-2-1234,s:rX,c:1700006400123*54\\{}",
+            SENTENCE
+        ))
+        .unwrap();
+        let (second, _) = split(&format!("\\g:2-2-1234*59\\{}", SENTENCE)).unwrap();
+        let first = first.unwrap();
+        let second = second.unwrap().merge(&first);
+        assert_eq!(first.group_id, Some(1234));
+        assert_eq!(second.group_id, Some(1234));
+        assert_eq!(second.source.as_deref(), Some("rX"));
+        assert_eq!(second.timestamp, first.timestamp);
+    }
+
+    #[test]
+    fn other_groups_are_not_merged() {
+        let first = parse_fields("g:1-2-1234,s:rX").unwrap();
+        let other = parse_fields("g:2-2-99").unwrap().merge(&first);
+        assert_eq!(other.source, None);
+    }
+
+    #[test]
+    fn malformed_group_is_an_error() {
+        assert!(parse_fields("g:1-2").is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use ais::messages::AisMessage;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Reports that arrived with "position not available" (lat 91 / lon 181)
+pub static POSITIONS_NOT_AVAILABLE: AtomicU64 = AtomicU64::new(0);
+// Reports whose coordinates were outside the valid range and got rejected
+pub static POSITIONS_OUT_OF_RANGE: AtomicU64 = AtomicU64::new(0);
+
+// Returns (latitude, longitude) if the reported position is usable, None otherwise.
+// The ais crate already maps the "not available" values to None, anything else
+// outside +-90 / +-180 is a corrupt report and is rejected here.
+pub fn validate_position(latitude: Option<f32>, longitude: Option<f32>) -> Option<(f64, f64)> {
+    let (latitude, longitude) = (latitude? as f64, longitude? as f64);
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return None;
+    }
+    Some((latitude, longitude))
+}
+
+// Counts a received report whose position won't be stored. Called once per message
+// as it is decoded, so writer retries and spool replays don't count it again.
+pub fn check_position(message: &AisMessage) {
+    let (mmsi, latitude, longitude) = match message {
+        AisMessage::PositionReport(m) => (m.mmsi, m.latitude, m.longitude),
+        AisMessage::BaseStationReport(m) => (m.mmsi, m.latitude, m.longitude),
+        AisMessage::StandardClassBPositionReport(m) => (m.mmsi, m.latitude, m.longitude),
+        AisMessage::ExtendedClassBPositionReport(m) => (m.mmsi, m.latitude, m.longitude),
+        AisMessage::AidToNavigationReport(m) => (m.mmsi, m.latitude, m.longitude),
+        AisMessage::LongRangeAisBroadcastMessage(m) => (m.mmsi, m.latitude, m.longitude),
+        _ => return,
+    };
+
+    let (Some(lat), Some(lon)) = (latitude, longitude) else {
+        POSITIONS_NOT_AVAILABLE.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    if validate_position(latitude, longitude).is_none() {
+        let rejected = POSITIONS_OUT_OF_RANGE.fetch_add(1, Ordering::Relaxed) + 1;
+        eprintln!(
+            "Rejected out-of-range position {}, {} from MMSI {} ({} rejected so far)",
+            lat, lon, mmsi, rejected
+        );
+    }
+}
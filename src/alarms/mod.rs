@@ -0,0 +1,246 @@
+pub mod notifier;
+
+use crate::ais::msg21::{AtonStatusPage, GeneralHealth, LightStatus, RaconStatus};
+use crate::client::envelope::{AisEnvelope, MessageSource};
+use ais::messages::AisMessage;
+use ais::messages::aid_to_navigation_report::AidToNavigationReport;
+use chrono::{DateTime, Utc};
+use notifier::Notifier;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender};
+
+// How many cleared alarms are kept around for the API
+pub const CLEARED_HISTORY: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum AlarmKind {
+    LightFailure, // Light failed or running at reduced range
+    RaconFault,
+    HealthAlarm, // The AtoN reports a general alarm
+    OffPosition,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum AlarmState {
+    Raised,
+    Acknowledged,
+    Cleared,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Alarm {
+    pub id: u64,
+    pub mmsi: u32,
+    pub name: String,
+    pub kind: AlarmKind,
+    pub state: AlarmState,
+    pub station: String, // Station that heard the report raising the alarm
+    pub raised_at: DateTime<Utc>,
+    pub last_reported_at: DateTime<Utc>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub cleared_at: Option<DateTime<Utc>>,
+    pub reports: u64, // Reports that showed the fault while the alarm was open
+}
+
+#[derive(Default)]
+struct Alarms {
+    restored: bool, // Reports are only checked once the saved alarms are back
+    next_id: u64,
+    open: HashMap<(u32, AlarmKind), Alarm>, // Raised or acknowledged
+    cleared: VecDeque<Alarm>,               // Newest first
+}
+
+// Raises an alarm the first time an AtoN reports a fault and clears it once a
+// report shows the fault gone. Repeated reports of an open fault only update it.
+#[derive(Default)]
+pub struct AlarmEngine {
+    alarms: Mutex<Alarms>,
+    notifiers: RwLock<Vec<Box<dyn Notifier>>>,
+    store: OnceLock<UnboundedSender<Alarm>>, // Every change to an alarm, to be saved
+}
+
+// What a single report says about each kind of fault: only the kinds it carries
+// are listed, with whether the fault is present. A status page other than 7 says
+// nothing about the light, RACON or health, so it leaves those alarms alone.
+fn faults(aton: &AidToNavigationReport, page: Option<AtonStatusPage>) -> Vec<(AlarmKind, bool)> {
+    let mut faults = vec![(AlarmKind::OffPosition, aton.off_position)];
+    if let Some(status) = page.and_then(|p| p.monitored()) {
+        faults.push((
+            AlarmKind::LightFailure,
+            status.light == LightStatus::FailOrReducedRange,
+        ));
+        faults.push((AlarmKind::RaconFault, status.racon == RaconStatus::Error));
+        faults.push((
+            AlarmKind::HealthAlarm,
+            status.health == GeneralHealth::Alarm,
+        ));
+    }
+    faults
+}
+
+impl AlarmEngine {
+    pub fn add_notifier(&self, notifier: Box<dyn Notifier>) {
+        self.notifiers.write().unwrap().push(notifier);
+    }
+
+    // Sends every change from now on to `store`, which keeps them in the database
+    pub fn store_to(&self, store: UnboundedSender<Alarm>) {
+        let _ = self.store.set(store);
+    }
+
+    fn store(&self, alarm: &Alarm) {
+        if let Some(store) = self.store.get() {
+            let _ = store.send(alarm.clone());
+        }
+    }
+
+    // Takes back the alarms saved by a previous run, so open ones and their
+    // acknowledgements survive a restart. New alarms are numbered after them.
+    pub fn restore(&self, saved: Vec<Alarm>) {
+        let mut alarms = self.alarms.lock().unwrap();
+        alarms.restored = true;
+        for alarm in saved {
+            alarms.next_id = alarms.next_id.max(alarm.id);
+            if alarm.state == AlarmState::Cleared {
+                alarms.cleared.push_back(alarm);
+            } else {
+                alarms.open.insert((alarm.mmsi, alarm.kind), alarm);
+            }
+        }
+        alarms
+            .cleared
+            .make_contiguous()
+            .sort_by_key(|a| std::cmp::Reverse(a.id));
+        alarms.cleared.truncate(CLEARED_HISTORY);
+    }
+
+    fn notify(&self, changed: &[Alarm]) {
+        let notifiers = self.notifiers.read().unwrap();
+        for alarm in changed {
+            for notifier in notifiers.iter() {
+                notifier.notify(alarm);
+            }
+        }
+    }
+
+    // Checks one AtoN report against the open alarms for that AtoN
+    pub fn evaluate(
+        &self,
+        source: &MessageSource,
+        aton: &AidToNavigationReport,
+        page: Option<AtonStatusPage>,
+    ) {
+        // A virtual AtoN has no light, RACON or position of its own to fail
+        if aton.virtual_aid {
+            return;
+        }
+
+        let faults = faults(aton, page);
+        let mut changed = Vec::new();
+        {
+            let mut alarms = self.alarms.lock().unwrap();
+            let alarms = &mut *alarms;
+            // Until then an ID handed out here could already belong to a saved alarm.
+            // A fault still there is raised by the AtoN's next report.
+            if !alarms.restored {
+                return;
+            }
+
+            for (kind, faulty) in faults {
+                let key = (aton.mmsi, kind);
+                match (alarms.open.get_mut(&key), faulty) {
+                    (Some(alarm), true) => {
+                        alarm.last_reported_at = source.received_at;
+                        alarm.reports += 1;
+                        self.store(alarm);
+                    }
+                    (Some(_), false) => {
+                        let mut alarm = alarms.open.remove(&key).unwrap();
+                        alarm.state = AlarmState::Cleared;
+                        alarm.cleared_at = Some(source.received_at);
+                        changed.push(alarm.clone());
+                        alarms.cleared.push_front(alarm);
+                        alarms.cleared.truncate(CLEARED_HISTORY);
+                    }
+                    (None, true) => {
+                        alarms.next_id += 1;
+                        let alarm = Alarm {
+                            id: alarms.next_id,
+                            mmsi: aton.mmsi,
+                            name: aton.name.to_string(),
+                            kind,
+                            state: AlarmState::Raised,
+                            station: source.station.clone(),
+                            raised_at: source.received_at,
+                            last_reported_at: source.received_at,
+                            acknowledged_at: None,
+                            cleared_at: None,
+                            reports: 1,
+                        };
+                        changed.push(alarm.clone());
+                        alarms.open.insert(key, alarm);
+                    }
+                    (None, false) => {}
+                }
+            }
+        }
+
+        for alarm in &changed {
+            self.store(alarm);
+        }
+        self.notify(&changed);
+    }
+
+    // Marks an open alarm as seen by a person; it stays open until the fault clears
+    pub fn acknowledge(&self, id: u64) -> Option<Alarm> {
+        let (alarm, acknowledged) = {
+            let mut alarms = self.alarms.lock().unwrap();
+            let alarm = alarms.open.values_mut().find(|a| a.id == id)?;
+            let acknowledged = alarm.state == AlarmState::Raised;
+            if acknowledged {
+                alarm.state = AlarmState::Acknowledged;
+                alarm.acknowledged_at = Some(Utc::now());
+            }
+            (alarm.clone(), acknowledged)
+        };
+        if acknowledged {
+            self.store(&alarm);
+            self.notify(std::slice::from_ref(&alarm));
+        }
+        Some(alarm)
+    }
+
+    // Raised and acknowledged alarms, oldest first
+    pub fn open(&self) -> Vec<Alarm> {
+        let alarms = self.alarms.lock().unwrap();
+        let mut open: Vec<_> = alarms.open.values().cloned().collect();
+        open.sort_by_key(|a| a.id);
+        open
+    }
+
+    // Recently cleared alarms, newest first
+    pub fn cleared(&self) -> Vec<Alarm> {
+        self.alarms
+            .lock()
+            .unwrap()
+            .cleared
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+// Pipeline stage in front of the database writer: checks AtoN reports and passes everything on
+pub async fn run(engine: Arc<AlarmEngine>, mut rx: Receiver<AisEnvelope>, tx: Sender<AisEnvelope>) {
+    while let Some(envelope) = rx.recv().await {
+        if let AisMessage::AidToNavigationReport(aton) = &envelope.message {
+            let page = AtonStatusPage::from_payload(&envelope.payload, envelope.fill_bits).ok();
+            engine.evaluate(&envelope.source, aton, page);
+        }
+        if tx.send(envelope).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use super::{Alarm, AlarmState};
+
+// Receives every alarm transition. Called from the receive pipeline, so an
+// implementation that talks to the network should hand the work off to a task.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, alarm: &Alarm);
+}
+
+// Writes alarm transitions to the log
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, alarm: &Alarm) {
+        let verb = match alarm.state {
+            AlarmState::Raised => "RAISED",
+            AlarmState::Acknowledged => "acknowledged",
+            AlarmState::Cleared => "cleared",
+        };
+        println!(
+            "Alarm #{} {}: {:?} on {} ({})",
+            alarm.id, verb, alarm.kind, alarm.name, alarm.mmsi
+        );
+    }
+}
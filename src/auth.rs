@@ -0,0 +1,41 @@
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+// Middleware letting a request through only with `Authorization: Bearer <token>`.
+// Without a configured token the routes behind it are switched off.
+pub async fn require_token(
+    State(token): State<Option<Arc<str>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = token else {
+        return (
+            StatusCode::FORBIDDEN,
+            "Station management and alarm acknowledgement are off; set api.token or AISMAR_API_TOKEN",
+        )
+            .into_response();
+    };
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if same(given.as_bytes(), token.as_bytes()) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Missing or wrong bearer token",
+        )
+            .into_response(),
+    }
+}
+
+// Compares without stopping at the first difference, so timing doesn't give the
+// token away a byte at a time
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
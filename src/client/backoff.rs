@@ -0,0 +1,51 @@
+use crate::config::ReconnectPolicy;
+use std::time::Duration;
+
+// Exponential backoff over consecutive failures of one endpoint
+pub struct Backoff {
+    policy: ReconnectPolicy,
+    failures: usize,
+}
+
+impl Backoff {
+    pub fn new(policy: ReconnectPolicy) -> Self {
+        Self {
+            policy,
+            failures: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    // Records a failure and returns how long to wait before the next attempt,
+    // or None once the policy's attempts are used up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        self.failures += 1;
+        if self
+            .policy
+            .max_attempts
+            .is_some_and(|max| self.failures > max)
+        {
+            return None;
+        }
+
+        let max_delay = self.policy.max_delay;
+        let growth = self
+            .policy
+            .multiplier
+            .max(1.0)
+            .powi(self.failures.min(64) as i32 - 1);
+        // Out of range means far beyond any sensible maximum
+        let base = Duration::try_from_secs_f64(self.policy.initial_delay.as_secs_f64() * growth)
+            .map_or(max_delay, |delay| delay.min(max_delay));
+        // Uniform factor in [1 - jitter, 1 + jitter], without going past the maximum
+        let jitter = self.policy.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 - jitter + 2.0 * jitter * rand::random::<f64>();
+        Some(
+            Duration::try_from_secs_f64(base.as_secs_f64() * factor)
+                .map_or(max_delay, |delay| delay.min(max_delay)),
+        )
+    }
+}
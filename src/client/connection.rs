@@ -1,272 +1,409 @@
-use crate::{ais::decoder, config::AisConfig};
-use ais::AisFragments;
-use ais::messages::AisMessage;
+use super::backoff::Backoff;
+use super::envelope::{AisEnvelope, MessageSource};
+use super::stats::{ConnectionState, StationStats};
+use crate::{
+    ais::{
+        bits::sixbit,
+        decoder::AisDecoder,
+        fragments::{ChecksumMismatch, FragmentStats},
+        tag_block, validation,
+    },
+    config::{AisConfig, Endpoint, Transport},
+    server::Feed,
+};
 use anyhow::Context;
-use serde::de;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::io::AsyncBufReadExt;
-use tokio::sync::Mutex;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
 use tokio::sync::mpsc::Sender;
-use tokio::task::{JoinError, JoinHandle};
-use tokio::{io::BufReader, net::TcpStream, time};
+use tokio::{
+    io::BufReader,
+    net::{TcpStream, UdpSocket},
+};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
 
-pub struct AisConnection {
-    stream: BufReader<TcpStream>,
+// Longest line kept: an 82-character sentence leaves plenty of room for a TAG block
+const MAX_LINE: usize = 1024;
+
+// An open link to an endpoint, ready to be read
+pub enum Input {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+    Serial(SerialStream),
+}
+
+impl Input {
+    pub async fn open(endpoint: &Endpoint) -> std::io::Result<Self> {
+        match &endpoint.transport {
+            Transport::Tcp => Ok(Input::Tcp(TcpStream::connect(&endpoint.address).await?)),
+            Transport::Udp { multicast } => {
+                let socket = UdpSocket::bind(&endpoint.address).await?;
+                match multicast {
+                    Some(IpAddr::V4(group)) => {
+                        socket.join_multicast_v4(*group, Ipv4Addr::UNSPECIFIED)?
+                    }
+                    Some(IpAddr::V6(group)) => socket.join_multicast_v6(group, 0)?,
+                    None => {}
+                }
+                Ok(Input::Udp(socket))
+            }
+            Transport::Serial { baud_rate } => {
+                let port = tokio_serial::new(&endpoint.address, *baud_rate).open_native_async()?;
+                Ok(Input::Serial(port))
+            }
+        }
+    }
+}
+
+// Reads from an endpoint for as long as the task lives, reconnecting after every
+// failure or disconnect with the configured backoff
+pub async fn keep_connected(
+    endpoint: Endpoint,
     config: Arc<AisConfig>,
-    decoder: Arc<Mutex<decoder::AisDecoder>>,
-    tx: tokio::sync::mpsc::Sender<AisMessage>, // Channel to send decoded results
+    fragment_stats: Arc<FragmentStats>,
+    raw_feed: Feed,
+    stats: Arc<StationStats>,
+    tx: Sender<AisEnvelope>,
+) {
+    let mut backoff = Backoff::new(endpoint.reconnect(&config).clone());
+    println!("Connecting to {} ({})", endpoint.station, endpoint.address);
+    loop {
+        stats.set_state(ConnectionState::Connecting);
+        match Input::open(&endpoint).await {
+            Ok(input) => {
+                println!("Connected to {}", endpoint.station);
+                stats.set_state(ConnectionState::Connected);
+                let lines_before = stats.lines();
+                let conn = AisConnection::new(
+                    endpoint.clone(),
+                    config.clone(),
+                    fragment_stats.clone(),
+                    raw_feed.clone(),
+                    stats.clone(),
+                    tx.clone(),
+                );
+                match conn.handle(input).await {
+                    Ok(()) => eprintln!("{} closed the connection", endpoint.station),
+                    Err(e) => eprintln!("Connection to {} failed: {}", endpoint.station, e),
+                }
+                // Anything received means the link worked; its failure starts backoff afresh
+                if stats.lines() > lines_before {
+                    backoff.reset();
+                }
+            }
+            Err(e) => eprintln!("Failed to connect to {}: {}", endpoint.station, e),
+        }
+
+        match backoff.next_delay() {
+            Some(delay) => {
+                println!(
+                    "Reconnecting to {} in {:.1} s",
+                    endpoint.station,
+                    delay.as_secs_f64()
+                );
+                stats.set_state(ConnectionState::BackingOff);
+                tokio::time::sleep(delay).await;
+                stats.record_reconnect();
+            }
+            None => {
+                eprintln!("Permanently failed to connect to {}", endpoint.station);
+                stats.set_state(ConnectionState::Failed);
+                return;
+            }
+        }
+    }
 }
 
-pub struct AisConnectionManager {
+pub struct AisConnection {
+    endpoint: Endpoint,
     config: Arc<AisConfig>,
-    decoder: Arc<tokio::sync::Mutex<decoder::AisDecoder>>,
-    handles: Vec<JoinHandle<()>>,
+    fragment_stats: Arc<FragmentStats>,
+    raw_feed: Feed, // Every line, for the re-broadcast server
+    stats: Arc<StationStats>,
+    tx: tokio::sync::mpsc::Sender<AisEnvelope>, // Channel to send decoded results
 }
 
 impl AisConnection {
-    pub fn new(stream: TcpStream, config: Arc<AisConfig>, tx: Sender<AisMessage>) -> Self {
+    pub fn new(
+        endpoint: Endpoint,
+        config: Arc<AisConfig>,
+        fragment_stats: Arc<FragmentStats>,
+        raw_feed: Feed,
+        stats: Arc<StationStats>,
+        tx: Sender<AisEnvelope>,
+    ) -> Self {
         Self {
-            stream: BufReader::new(stream),
+            endpoint,
             config,
-            decoder: Arc::new(Mutex::new(decoder::AisDecoder::new())),
+            fragment_stats,
+            raw_feed,
+            stats,
             tx,
         }
     }
 
-    pub async fn handle(mut self) -> anyhow::Result<()> {
-        let mut buffer = String::new();
+    pub async fn handle(self, input: Input) -> anyhow::Result<()> {
+        match input {
+            Input::Tcp(stream) => self.read_lines(BufReader::new(stream)).await,
+            Input::Udp(socket) => self.read_datagrams(socket).await,
+            Input::Serial(port) => self.read_lines(BufReader::new(port)).await,
+        }
+    }
+
+    fn read_timeout(&self) -> Duration {
+        self.endpoint.read_timeout(&self.config)
+    }
+
+    pub(super) fn decoder(&self) -> AisDecoder {
+        AisDecoder::new(self.config.fragment_timeout, self.fragment_stats.clone())
+    }
+
+    async fn read_lines<R: AsyncBufRead + Unpin>(&self, mut stream: R) -> anyhow::Result<()> {
+        let mut decoder = self.decoder();
+        let mut buffer = Vec::new();
 
         loop {
             buffer.clear();
-            let read_result =
-                tokio::time::timeout(self.config.read_timeout, self.stream.read_line(&mut buffer))
-                    .await;
-
-            match read_result {
-                Ok(Ok(0)) => break, // Clean disconnect
-                Ok(Ok(_)) => {
-                    let line = buffer.trim_end();
-                    let mut decoder = self.decoder.lock().await;
-                    match decoder.parser.parse(line.as_bytes(), true) {
-                        Ok(AisFragments::Complete(sentence)) => {
-                            if let Some(msg) = sentence.message {
-                                if let Err(e) =
-                                    decoder.handle_message(msg, line, self.tx.to_owned()).await
-                                {
-                                    eprintln!("Message handling error: {}", e);
-                                }
-                            }
-                        }
-                        Err(e) => eprintln!("Parsing error: {}", e),
-                        _ => {} // Handle incomplete fragments if needed
+            if self.read_line(&mut stream, &mut buffer).await? == 0 {
+                break; // Clean disconnect
+            }
+            if buffer.len() == MAX_LINE && !buffer.ends_with(b"\n") {
+                // Noise without line breaks, e.g. a serial port at the wrong baud rate.
+                // Drop it and carry on after the next line break.
+                eprintln!(
+                    "Skipping a line from {} longer than {} bytes",
+                    self.endpoint.station, MAX_LINE
+                );
+                self.stats.record_parse_error();
+                loop {
+                    buffer.clear();
+                    let read = self.read_line(&mut stream, &mut buffer).await?;
+                    if read == 0 || buffer.ends_with(b"\n") {
+                        break;
                     }
                 }
-                Ok(Err(e)) => return Err(e).context("Read error"),
-                Err(_) => return Err(anyhow::anyhow!("Read timeout")),
+                continue;
             }
+            // Line noise on a serial link is not valid UTF-8; the bad line
+            // fails its checksum instead of dropping the connection
+            let line = String::from_utf8_lossy(&buffer);
+            self.process_line(&mut decoder, line.trim_end(), Utc::now())
+                .await
         }
 
         Ok(())
     }
-}
 
-impl AisConnectionManager {
-    pub fn new(config: AisConfig) -> Self {
-        Self {
-            config: Arc::new(config),
-            decoder: Arc::new(tokio::sync::Mutex::new(decoder::AisDecoder::new())),
-            handles: Vec::new(),
+    // Reads up to and including the next line break, but no more than MAX_LINE bytes
+    async fn read_line<R: AsyncBufRead + Unpin>(
+        &self,
+        stream: &mut R,
+        buffer: &mut Vec<u8>,
+    ) -> anyhow::Result<usize> {
+        let mut limited = stream.take(MAX_LINE as u64);
+        let read = limited.read_until(b'\n', buffer);
+        match tokio::time::timeout(self.read_timeout(), read).await {
+            Ok(result) => result.context("Read error"),
+            Err(_) => Err(anyhow::anyhow!("Read timeout")),
         }
     }
 
-    pub async fn start(&mut self) -> anyhow::Result<()> {
-        let (tx, mut rx) = mpsc::channel(100);
-
-        for endpoint in &self.config.endpoints {
-            let endpoint = endpoint.clone();
-            let config = self.config.clone();
-            let decoder = self.decoder.clone();
-            let tx_clone = tx.clone(); // Clone sender for each connection
+    // A datagram holds one or more sentences. Every sender gets its own decoder so
+    // fragments from different receivers on the same port don't mix.
+    async fn read_datagrams(&self, socket: UdpSocket) -> anyhow::Result<()> {
+        // One decoder per sender, with when it was last heard from
+        let mut decoders: HashMap<SocketAddr, (AisDecoder, Instant)> = HashMap::new();
+        let mut last_sweep = Instant::now();
+        let mut buffer = vec![0u8; 65536];
+        let mut quiet = false;
 
-            let handle = tokio::spawn(async move {
-                let mut attempt = 0;
-
-                loop {
-                    match TcpStream::connect(&endpoint).await {
-                        Ok(stream) => {
-                            attempt = 0;
-                            let connection =
-                                AisConnection::new(stream, config.clone(), tx_clone.clone());
-                            if let Err(e) = connection.handle().await {
-                                eprintln!("Connection to {} failed: {}", endpoint, e);
-                            }
-                        }
-                        Err(e) => {
-                            attempt += 1;
-                            if attempt > config.max_reconnect_attempts {
-                                eprintln!("Permanently failed to connect to {}", endpoint);
-                                break;
-                            }
-                            tokio::time::sleep(config.reconnect_delay).await;
-                        }
+        loop {
+            let received =
+                tokio::time::timeout(self.read_timeout(), socket.recv_from(&mut buffer)).await;
+            let (len, peer) = match received {
+                Ok(result) => result.context("Read error")?,
+                Err(_) => {
+                    // Nothing to reconnect: rebinding wouldn't bring senders back, and
+                    // the socket must stay open for when they return
+                    if !quiet {
+                        eprintln!(
+                            "No datagrams for {} in {} s, still listening",
+                            self.endpoint.station,
+                            self.read_timeout().as_secs()
+                        );
+                        quiet = true;
                     }
+                    continue;
                 }
-            });
-
-            self.handles.push(handle);
-        }
-
-        // Monitor tasks
-        tokio::spawn(async move {
-            while let Some(result) = rx.recv().await {
-                println!("Received: {:?}", result);
-
-                /* match msg {
-                    AisMessage::PositionReport(pos) => {
-                         println!("[Type {}] Vessel {}: {:?} {:?} | SOG: {} kt | Nav Status: {:?}, name {}",
-                            pos.message_type,
-                            pos.mmsi,
-                            pos.latitude.unwrap_or(0.0),
-                            pos.longitude.unwrap_or(0.0),
-                            pos.speed_over_ground.unwrap_or(0.0),
-                            pos.navigation_status.unwrap_or(ais::messages::position_report::NavigationStatus::Unknown(2)),
-                            pos.name()
+            };
+            quiet = false;
+            let received_at = Utc::now();
+            let now = Instant::now();
 
+            // Senders come and go (boats, DHCP), so forget the ones that went quiet.
+            // Anything they left half-received has timed out by then.
+            let idle = self.config.fragment_timeout;
+            if now.duration_since(last_sweep) >= idle {
+                decoders.retain(|_, (decoder, last_heard)| {
+                    let keep = now.duration_since(*last_heard) < idle;
+                    if !keep {
+                        decoder.expire(now);
+                    }
+                    keep
+                });
+                last_sweep = now;
+            }
 
-                        );
+            let (decoder, last_heard) = decoders
+                .entry(peer)
+                .or_insert_with(|| (self.decoder(), now));
+            *last_heard = now;
+            let datagram = String::from_utf8_lossy(&buffer[..len]);
+            for line in datagram.lines().map(str::trim_end) {
+                if !line.is_empty() {
+                    self.process_line(decoder, line, received_at).await;
+                }
+            }
+        }
+    }
 
+    pub(super) async fn process_line(
+        &self,
+        decoder: &mut AisDecoder,
+        line: &str,
+        received_at: DateTime<Utc>,
+    ) {
+        self.stats.record_line();
+        if self.raw_feed.receiver_count() > 0 {
+            let _ = self.raw_feed.send(line.into());
+        }
+        let (tag, line) = match tag_block::split(line) {
+            Ok(split) => split,
+            Err(e) => {
+                eprintln!("TAG block error from {}: {}", self.endpoint.station, e);
+                self.record_error(&e);
+                return;
+            }
+        };
+        match decoder.decode(line, tag) {
+            Ok(Some((assembled, message))) => {
+                if let Some(message_type) = assembled.payload.bytes().next().and_then(sixbit) {
+                    self.stats.record_message(message_type, received_at);
+                    if !self.endpoint.accepts(message_type) {
+                        return; // Counted as received, but this station doesn't store the type
                     }
-                    AisMessage::BaseStationReport(bs) => {
-                        /*  println!("[Type {}] Base Station {}: {:?} UTC",
-                            bs.message_type,
-                            bs.mmsi,
-                            bs.hour
-                        ); */
-                    }
-                    AisMessage::StaticAndVoyageRelatedData(sdv) => {
-                        /*  println!("[Type {}] Vessel {}: {} → {}",
-                            sdv.message_type,
-                            sdv.mmsi,
-                            sdv.vessel_name,
-                            sdv.destination
-                        ); */
-                    }
-                    AisMessage::StandardClassBPositionReport(scb) => {
-                        /*  println!("[Type {}] Class B {}: {:?} {:?} COG: {}",
-                            scb.message_type,
-                            scb.mmsi,
-                            scb.latitude.unwrap_or(0.0),
-                            scb.longitude.unwrap_or(0.0),
-                            scb.course_over_ground.unwrap_or(0.0)
-                        ); */
-                    }
-                    AisMessage::ExtendedClassBPositionReport(ecb) => {
-                        /* println!("[Type {}] Ext. Class B {}: {:?} {:?}",
-                            ecb.message_type,
-                            ecb.mmsi,
-                            ecb.latitude.unwrap_or(0.0),
-                            ecb.longitude.unwrap_or(0.0)
-                        ); */
-                    }
-                    AisMessage::DataLinkManagementMessage(dlm) => {
-                        /*  println!("[Type {}] DLM {}: Reservation {}",
-                            dlm.message_type,
-                            dlm.mmsi,
-                            dlm.reservations.len()
-                        ); */
-                    }
-                    AisMessage::AidToNavigationReport(aton) => {
-                         let (status_byte, page_id) = self.extract_aton_status(raw_sentence)? ;
-                            // Parse status components for Page ID 7 (Most common operational status)
-                           if page_id == 7 {
-                                let (racon_status, light_status, health) = parse_aton_status(status_byte.reverse_bits());
-                                println!(
-                                    "[Type {}] AtoN {}: {} ({:?})",
-                                    aton.message_type, aton.mmsi, aton.name, aton.aid_type
-                                );
-                                println!(
-                                    "  → Status: Page {} | RACON: {:?} | Light: {:?} | Off-position: {}",
-                                    page_id, racon_status, light_status, aton.off_position
-                                );
-
+                }
+                validation::check_position(&message);
+                let tag = assembled.tag.unwrap_or_default();
+                let envelope = AisEnvelope {
+                    source: MessageSource {
+                        station: self.endpoint.station.clone(),
+                        heard_by: vec![self.endpoint.station.clone()],
+                        received_at,
+                        tag_source: tag.source,
+                        tag_time: tag.timestamp,
+                    },
+                    payload: assembled.payload,
+                    fill_bits: assembled.fill_bits,
+                    framing: assembled.framing,
+                    message,
+                };
+                if let Err(e) = decoder.handle_message(envelope, self.tx.to_owned()).await {
+                    eprintln!("Message handling error: {}", e);
+                }
+            }
+            Ok(None) => {} // Waiting for the rest of a multi-sentence message
+            Err(e) => {
+                eprintln!("Parsing error: {}", e);
+                self.record_error(&e);
+            }
+        }
+    }
 
-                            /* println!("[Type {}] AtoN {}: {} ({:?})",
-                                aton.message_type,
-                                aton.mmsi,
-                                aton.name,
-                                aton.aid_type
-                            );
-                            println!("  → Status: Page {} | RACON: {:?} | Light: {:?} | Off-position: {}",
-                                page_id,
-                                racon_status,
-                                light_status,
-                                aton.off_position
-                            ); */
-                        }
+    fn record_error(&self, e: &anyhow::Error) {
+        if e.downcast_ref::<ChecksumMismatch>().is_some() {
+            self.stats.record_checksum_error();
+        } else {
+            self.stats.record_parse_error();
+        }
+    }
+}
 
-                        /* if (43.0..44.0).contains(&aton.latitude.unwrap_or(0 as f32)) &&
-                           (16.0..17.0).contains(&aton.longitude.unwrap_or(0.0)) {
-                            println!("Dalmatian AtoN: {}", aton.name);
-                        } */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ais::messages::AisMessage;
+    use tokio::io::AsyncWriteExt;
+    use tokio::sync::mpsc;
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serial_line_noise_keeps_the_port_open() {
+        let (mut device, port) = SerialStream::pair().unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        let stats = Arc::new(StationStats::default());
+        let conn = AisConnection::new(
+            Endpoint::serial("Serial", "/dev/null", 38400),
+            Arc::new(AisConfig::default()),
+            Arc::new(FragmentStats::default()),
+            tokio::sync::broadcast::channel(10).0,
+            stats.clone(),
+            tx,
+        );
+        let reader = tokio::spawn(conn.handle(Input::Serial(port)));
 
-                       /*  println!("[Type {}] AtoN {}: {} ({:?})",
-                            aton.message_type,
-                            aton.mmsi,
-                            aton.name,
-                            aton.aid_type,);    */
+        device.write_all(b"\xff\xfe!AIVDM\x80\r\n").await.unwrap();
+        device
+            .write_all(b"!AIVDM,1,1,,A,13u?etPv2;0n:dDPwUM1U1Cb069D,0*24\r\n")
+            .await
+            .unwrap();
 
-                   }
-                    AisMessage::StaticDataReport(sdr) => {
-                        /* println!("[Type {}] Static Data {}: {:?}",
-                            sdr.message_type,
-                            sdr.mmsi,
-                            sdr.message_part
-                        ); */
-                    }
-                    AisMessage::SafetyRelatedBroadcastMessage(srm) => {
-                        /* println!("[Type {}] Safety Message from {}: {}",
-                            srm.message_type,
-                            srm.mmsi,
-                            srm.text
-                        ); */
-                    }
-                    AisMessage::BinaryAcknowledgeMessage(ba) => { /* Type 7  TODO*/ }
+        let envelope = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("message after the noise")
+            .unwrap();
+        let AisMessage::PositionReport(report) = envelope.message else {
+            panic!("expected a position report");
+        };
+        assert_eq!(report.mmsi, 265547250);
+        assert!(!reader.is_finished());
+        let snapshot = stats.snapshot("Serial");
+        assert_eq!((snapshot.lines, snapshot.parse_errors), (2, 1));
+        reader.abort();
+    }
 
-                    AisMessage::BinaryBroadcastMessage(bbm) => {
-                        /* println!("[Type {}] Binary Broadcast {}: {} bytes",
-                            bbm.message_type,
-                            bbm.mmsi,
-                            bbm.data.len()
-                        ); */
-                    }
-                    AisMessage::UtcDateResponse(udr) => {
-                        /*  println!("[Type {}] UTC Date- hour: {}{}:{} UTC",
-                            udr.message_type,
-                            udr.hour,
-                            udr.minute.unwrap_or(0),
-                            udr.second.unwrap_or(0),
-                        ); */
-                    }
-                    AisMessage::AssignmentModeCommand(amc) => {
-                        /*  println!("[Type {}] AMC for MMSI {}",
-                            amc.message_type,
-                            amc.mmsi
-                        ); */
-                    }
-                    // Add other message types as needed
-                    _ => println!("[Type s] Unhandled message format",),
-                } */
-            }
-        });
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn overlong_line_is_skipped_up_to_the_next_line_break() {
+        let (mut device, port) = SerialStream::pair().unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        let stats = Arc::new(StationStats::default());
+        let conn = AisConnection::new(
+            Endpoint::serial("Serial", "/dev/null", 38400),
+            Arc::new(AisConfig::default()),
+            Arc::new(FragmentStats::default()),
+            tokio::sync::broadcast::channel(10).0,
+            stats.clone(),
+            tx,
+        );
+        let reader = tokio::spawn(conn.handle(Input::Serial(port)));
 
-        Ok(())
-    }
+        device.write_all(&[b'x'; 3 * MAX_LINE]).await.unwrap();
+        device
+            .write_all(b"\r\n!AIVDM,1,1,,A,13u?etPv2;0n:dDPwUM1U1Cb069D,0*24\r\n")
+            .await
+            .unwrap();
 
-    pub async fn shutdown(self) {
-        for handle in self.handles {
-            handle.abort();
-        }
+        let envelope = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("message after the long line")
+            .unwrap();
+        assert_eq!(envelope.payload, "13u?etPv2;0n:dDPwUM1U1Cb069D");
+        let snapshot = stats.snapshot("Serial");
+        assert_eq!((snapshot.lines, snapshot.parse_errors), (1, 1));
+        reader.abort();
     }
 }
@@ -0,0 +1,97 @@
+use super::envelope::AisEnvelope;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{self, Instant};
+
+// Collapses copies of the same payload heard by several stations into one envelope.
+// The first copy is held back for `window`; every copy arriving in the meantime only
+// adds its station to `heard_by`. Once the window has passed the envelope is released.
+pub struct Deduplicator {
+    window: Duration,
+    pending: HashMap<String, AisEnvelope>,
+    expiry: VecDeque<(Instant, String)>, // Insertion order is also expiry order
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: HashMap::new(),
+            expiry: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, envelope: AisEnvelope) {
+        if let Some(first) = self.pending.get_mut(&envelope.payload) {
+            let station = envelope.source.station;
+            if !first.source.heard_by.contains(&station) {
+                first.source.heard_by.push(station);
+            }
+            return;
+        }
+
+        let key = envelope.payload.clone();
+        self.expiry
+            .push_back((Instant::now() + self.window, key.clone()));
+        self.pending.insert(key, envelope);
+    }
+
+    // Removes and returns every envelope whose window has passed, oldest first
+    pub fn expired(&mut self, now: Instant) -> Vec<AisEnvelope> {
+        let mut released = Vec::new();
+        while let Some((deadline, _)) = self.expiry.front() {
+            if *deadline > now {
+                break;
+            }
+            let (_, key) = self.expiry.pop_front().unwrap();
+            if let Some(envelope) = self.pending.remove(&key) {
+                released.push(envelope);
+            }
+        }
+        released
+    }
+
+    pub fn drain(&mut self) -> Vec<AisEnvelope> {
+        self.expired(Instant::now() + self.window)
+    }
+}
+
+// Sits between the connection tasks and the database writer
+pub async fn run(window: Duration, mut rx: Receiver<AisEnvelope>, tx: Sender<AisEnvelope>) {
+    // A zero window disables deduplication
+    if window.is_zero() {
+        while let Some(envelope) = rx.recv().await {
+            if tx.send(envelope).await.is_err() {
+                return;
+            }
+        }
+        return;
+    }
+
+    let mut dedup = Deduplicator::new(window);
+    // Replay scales the window down by its speed, keep the period from rounding to zero
+    let mut tick = time::interval((window / 4).max(Duration::from_millis(1)));
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Some(envelope) => dedup.push(envelope),
+                None => break,
+            },
+            now = tick.tick() => {
+                for envelope in dedup.expired(now) {
+                    if tx.send(envelope).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // All connections are gone, release whatever is still held back
+    for envelope in dedup.drain() {
+        if tx.send(envelope).await.is_err() {
+            return;
+        }
+    }
+}
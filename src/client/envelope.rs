@@ -0,0 +1,27 @@
+use crate::ais::fragments::Framing;
+use ais::messages::AisMessage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Where and when a message was received
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MessageSource {
+    pub station: String,       // Station that delivered the copy we keep
+    pub heard_by: Vec<String>, // Every station that heard the same payload
+    pub received_at: DateTime<Utc>,
+    // From the line's NMEA TAG block, when the feed sends one
+    #[serde(default)]
+    pub tag_source: Option<String>,
+    #[serde(default)]
+    pub tag_time: Option<DateTime<Utc>>,
+}
+
+// A decoded message as it travels from a connection task to the database writer
+#[derive(Debug)]
+pub struct AisEnvelope {
+    pub source: MessageSource,
+    pub payload: String, // Armored payload, all fragments concatenated
+    pub fill_bits: u8,
+    pub framing: Framing, // Talker, VDM/VDO and channel it came in with
+    pub message: AisMessage,
+}
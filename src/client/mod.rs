@@ -1,99 +1,215 @@
 // Declare the connection submodule
+pub mod backoff;
 pub mod connection;
-use crate::config::AisConfig;
-use crate::db::database::insert_position_report;
-use ais::messages::{AisMessage, position_report};
+pub mod dedup;
+pub mod envelope;
+pub mod replay;
+pub mod stats;
+pub mod supervisor;
+use crate::ais::fragments::FragmentStats;
+use crate::alarms::{self, AlarmEngine};
+use crate::config::{AisConfig, Endpoint};
+use crate::db::writer::{self, WriterStats};
+use crate::db::{self, station_stats};
+use crate::metrics::Queues;
+use crate::server::{self, Feed};
 use connection::AisConnection;
-use sqlx::{PgPool, pool};
+use envelope::AisEnvelope;
+use replay::{Pace, ReplayOptions};
+use stats::{StationRegistry, StationStats};
 use std::sync::Arc;
-use tokio::sync::mpsc::{self, Sender};
-use tokio::{net::TcpStream, task::JoinHandle, time};
+use supervisor::Supervisor;
+use tokio::sync::mpsc;
+use tokio::{task::JoinHandle, time};
 
 pub struct AisClient {
     config: Arc<AisConfig>,
-    handles: Vec<JoinHandle<()>>, // Store handles for each connection task
+    supervisor: Option<Arc<Supervisor>>, // Owns the connection tasks while running
+    watcher: Option<JoinHandle<()>>,     // Reloads the config file into the supervisor
+    writer_stats: Arc<WriterStats>,
+    fragment_stats: Arc<FragmentStats>,
+    alarms: Arc<AlarmEngine>,
+    stations: Arc<StationRegistry>,
+    queues: Arc<Queues>,
+    raw_feed: Feed,      // Lines as received, for the re-broadcast server
+    filtered_feed: Feed, // Deduplicated messages, for the re-broadcast server
 }
 
 impl AisClient {
     pub fn new(config: AisConfig) -> Self {
+        let raw_feed = server::feed(config.server.as_ref());
+        let filtered_feed = server::feed(config.server.as_ref());
         Self {
             config: Arc::new(config),
-            handles: Vec::new(),
+            supervisor: None,
+            watcher: None,
+            writer_stats: Arc::new(WriterStats::default()),
+            fragment_stats: Arc::new(FragmentStats::default()),
+            alarms: Arc::new(AlarmEngine::default()),
+            stations: Arc::new(StationRegistry::default()),
+            queues: Arc::new(Queues::default()),
+            raw_feed,
+            filtered_feed,
         }
     }
 
+    pub fn writer_stats(&self) -> Arc<WriterStats> {
+        self.writer_stats.clone()
+    }
+
+    pub fn fragment_stats(&self) -> Arc<FragmentStats> {
+        self.fragment_stats.clone()
+    }
+
+    pub fn alarms(&self) -> Arc<AlarmEngine> {
+        self.alarms.clone()
+    }
+
+    pub fn stations(&self) -> Arc<StationRegistry> {
+        self.stations.clone()
+    }
+
+    pub fn queues(&self) -> Arc<Queues> {
+        self.queues.clone()
+    }
+
+    // Set once `run` has started the connections
+    pub fn supervisor(&self) -> Option<Arc<Supervisor>> {
+        self.supervisor.clone()
+    }
+
     pub async fn run(&mut self, pool: Arc<sqlx::PgPool>) -> anyhow::Result<()> {
-        let (tx, mut rx) = mpsc::channel(100); // Create a channel for communication
+        // Create channels for communication
+        let (tx, raw_rx) = mpsc::channel::<AisEnvelope>(self.config.channel_capacity);
+        let (dedup_tx, dedup_rx) = mpsc::channel::<AisEnvelope>(self.config.channel_capacity);
+        let (forward_tx, forward_rx) = mpsc::channel::<AisEnvelope>(self.config.channel_capacity);
+        let (alarm_tx, rx) = mpsc::channel::<AisEnvelope>(self.config.channel_capacity);
         let pool = pool.clone();
-        // Spawn a connection task for each endpoint
-        for endpoint in &self.config.endpoints {
-            let endpoint = endpoint.clone();
-            let config = self.config.clone();
-            let tx_clone = tx.clone(); // Clone sender for each connection
-
-            let handle = tokio::spawn(async move {
-                let mut attempt = 0;
-                println!("Connecting to {}", endpoint);
-                loop {
-                    match TcpStream::connect(&endpoint).await {
-                        Ok(stream) => {
-                            attempt = 0;
-                            println!("Connected to {}", endpoint);
-
-                            // Create a new AisConnection and handle it
-                            let conn = AisConnection::new(stream, config.clone(), tx_clone.clone());
-                            if let Err(e) = conn.handle().await {
-                                eprintln!("Connection to {} failed: {}", endpoint, e);
-                            }
-                        }
-                        Err(e) => {
-                            attempt += 1;
-                            eprintln!("Failed to connect to {}: {}", endpoint, e);
-
-                            if attempt > config.max_reconnect_attempts {
-                                eprintln!("Permanently failed to connect to {}", endpoint);
-                                break;
-                            }
-
-                            time::sleep(config.reconnect_delay).await;
-                        }
-                    }
+        self.queues.watch("dedup", &tx);
+        self.queues.watch("forward", &dedup_tx);
+        self.queues.watch("alarms", &forward_tx);
+        self.queues.watch("writer", &alarm_tx);
+
+        // Drop copies of the same sentence heard by overlapping stations
+        tokio::spawn(dedup::run(self.config.dedup_window, raw_rx, dedup_tx));
+
+        // Pass the deduplicated stream on to downstream clients
+        tokio::spawn(server::forward(
+            dedup_rx,
+            forward_tx,
+            self.filtered_feed.clone(),
+        ));
+        if let Some(server_config) = self.config.server.clone() {
+            let raw_feed = self.raw_feed.clone();
+            let filtered_feed = self.filtered_feed.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server::run(server_config, raw_feed, filtered_feed).await {
+                    eprintln!("Re-broadcast server failed: {}", e);
                 }
             });
-
-            self.handles.push(handle);
         }
 
-        // Monitor received messages from all connections
-        tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                //println!("Received decoded message: {:?}", message); for debug
-
-                match message {
-                    AisMessage::PositionReport(pos) => {
-                        let ms = format!(
-                            "type: {} MMSI: {} lat: {} lon: {}",
-                            pos.message_type,
-                            pos.mmsi,
-                            pos.latitude.unwrap_or(0.0),
-                            pos.longitude.unwrap_or(0.0)
-                        );
-                        println!("{}", ms);
-                        if let Err(e) = insert_position_report(&pool.clone(), pos).await {
-                            eprintln!("Failed to insert into database: {}", e);
-                        }
-                    }
-                    _ => (), //println!("[Type s] Unhandled message format",),
+        // Watch AtoN reports for faults before they are written, carrying on with
+        // the alarms of the last run once they are loaded. Every change to an alarm is saved.
+        let (store_tx, store_rx) = mpsc::unbounded_channel();
+        self.alarms.store_to(store_tx);
+        tokio::spawn(db::alarms::run(pool.clone(), self.alarms.clone(), store_rx));
+        tokio::spawn(alarms::run(self.alarms.clone(), forward_rx, alarm_tx));
+
+        // Connect to every station, and follow changes to the config file
+        let supervisor = Arc::new(
+            Supervisor::new(
+                self.config.clone(),
+                self.fragment_stats.clone(),
+                self.raw_feed.clone(),
+                self.stations.clone(),
+                tx,
+            )
+            .await,
+        );
+        self.watcher = Some(tokio::spawn(supervisor.clone().watch()));
+        self.supervisor = Some(supervisor);
+
+        // Keep a time series of every station's reception
+        tokio::spawn(station_stats::run(
+            pool.clone(),
+            self.stations.clone(),
+            self.config.stats_interval,
+            self.config.stats_retention,
+        ));
+
+        // Write received messages from all connections in batches
+        tokio::spawn(writer::run(
+            pool,
+            rx,
+            self.config.clone(),
+            self.writer_stats.clone(),
+        ));
+
+        Ok(())
+    }
+
+    // Runs recorded NMEA files through decoding, deduplication and the writer, then
+    // waits for everything to be written. Historic AtoN reports don't raise alarms.
+    pub async fn replay(
+        &mut self,
+        pool: Arc<sqlx::PgPool>,
+        options: ReplayOptions,
+    ) -> anyhow::Result<()> {
+        let (tx, raw_rx) = mpsc::channel::<AisEnvelope>(self.config.channel_capacity);
+        let (dedup_tx, rx) = mpsc::channel::<AisEnvelope>(self.config.channel_capacity);
+
+        // The window is wall-clock time, so it shrinks with the replay speed; unpaced
+        // there is no telling copies from repeats and nothing is deduplicated
+        let dedup_window = match options.pace {
+            Pace::Unpaced => time::Duration::ZERO,
+            Pace::Speed(speed) => self.config.dedup_window.div_f64(speed),
+        };
+        let dedup = tokio::spawn(dedup::run(dedup_window, raw_rx, dedup_tx));
+        let writer = tokio::spawn(writer::run(
+            pool,
+            rx,
+            self.config.clone(),
+            self.writer_stats.clone(),
+        ));
+
+        for path in &options.files {
+            let endpoint = Endpoint::new(&options.station, &path.display().to_string());
+            let conn = AisConnection::new(
+                endpoint,
+                self.config.clone(),
+                self.fragment_stats.clone(),
+                self.raw_feed.clone(),
+                // Old files must not count as live reception in the station's stats
+                Arc::new(StationStats::default()),
+                tx.clone(),
+            );
+            match replay::replay_file(&conn, path, options.pace).await {
+                Ok(sentences) => {
+                    println!("Replayed {} sentences from {}", sentences, path.display())
                 }
+                Err(e) => eprintln!("Replay of {} failed: {:#}", path.display(), e),
             }
-        });
+        }
 
+        // Closing the channel lets dedup and the writer drain and stop
+        drop(tx);
+        dedup.await?;
+        writer.await?;
+        let s = self.writer_stats.snapshot();
+        println!(
+            "Replay done: {} rows written, {} failed, {} spooled",
+            s.rows, s.failed_rows, s.spooled
+        );
         Ok(())
     }
 
     pub async fn shutdown(self) {
-        for handle in self.handles {
-            handle.abort(); // Gracefully abort all tasks
+        if let Some(watcher) = self.watcher {
+            watcher.abort();
+        }
+        if let Some(supervisor) = self.supervisor {
+            supervisor.stop();
         }
     }
 }
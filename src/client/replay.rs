@@ -0,0 +1,152 @@
+use super::connection::AisConnection;
+use crate::ais::tag_block;
+use anyhow::{Context, bail};
+use async_compression::tokio::bufread::GzipDecoder;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::time::{self, Instant};
+
+// How fast to feed a recording through the pipeline
+#[derive(Clone, Copy, Debug)]
+pub enum Pace {
+    Unpaced,    // As fast as the writer keeps up
+    Speed(f64), // 1.0 is the original rate, 10.0 ten times faster
+}
+
+#[derive(Debug)]
+pub struct ReplayOptions {
+    pub files: Vec<PathBuf>,
+    pub station: String, // Recorded as the receiving station of every message
+    pub pace: Pace,
+}
+
+impl ReplayOptions {
+    // replay [--station NAME] [--speed N | --realtime] FILE...
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let mut options = Self {
+            files: Vec::new(),
+            station: "replay".to_string(),
+            pace: Pace::Unpaced,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--station" => {
+                    options.station = args.next().context("--station needs a name")?.clone();
+                }
+                "--speed" => {
+                    let speed: f64 = args
+                        .next()
+                        .context("--speed needs a factor")?
+                        .parse()
+                        .context("Invalid --speed factor")?;
+                    if speed.is_nan() || speed <= 0.0 {
+                        bail!("--speed must be above zero");
+                    }
+                    options.pace = Pace::Speed(speed);
+                }
+                "--realtime" => options.pace = Pace::Speed(1.0),
+                flag if flag.starts_with("--") => bail!("Unknown replay option {}", flag),
+                file => options.files.push(PathBuf::from(file)),
+            }
+        }
+        if options.files.is_empty() {
+            bail!("Usage: replay [--station NAME] [--speed N | --realtime] FILE...");
+        }
+        Ok(options)
+    }
+}
+
+// Loggers put their own time in front of the sentence: seconds since the epoch
+// or an ISO 8601 / "YYYY-MM-DD HH:MM:SS" date, optionally followed by ; or ,
+fn parse_leading_timestamp(prefix: &str) -> Option<DateTime<Utc>> {
+    let prefix = prefix.trim().trim_end_matches([';', ',', ':']).trim();
+    if let Ok(secs) = prefix.parse::<f64>() {
+        return if secs > 100_000_000_000.0 {
+            DateTime::from_timestamp_millis(secs as i64)
+        } else {
+            DateTime::from_timestamp_millis((secs * 1000.0) as i64)
+        };
+    }
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(prefix) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(prefix, format).ok())
+        .map(|timestamp| timestamp.and_utc())
+}
+
+// Splits off a leading timestamp and returns the time the line was received along
+// with the sentence (TAG block included). The TAG block's receiver time wins over
+// the logger's.
+fn split_line(line: &str) -> Option<(Option<DateTime<Utc>>, &str)> {
+    if line.starts_with('#') {
+        return None;
+    }
+    let start = line.find(['\\', '!', '$'])?;
+    let (prefix, sentence) = line.split_at(start);
+    let tag_time = match tag_block::split(sentence) {
+        Ok((Some(tag), _)) => tag.timestamp,
+        _ => None,
+    };
+    Some((
+        tag_time.or_else(|| parse_leading_timestamp(prefix)),
+        sentence,
+    ))
+}
+
+async fn open(path: &Path) -> anyhow::Result<Box<dyn AsyncBufRead + Unpin + Send>> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let file = BufReader::new(file);
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut gzip = GzipDecoder::new(file);
+        gzip.multiple_members(true); // Logs rotated with `cat a.gz b.gz` still read through
+        Ok(Box::new(BufReader::new(gzip)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+// Feeds one recording through the connection's decoding path and returns the
+// number of sentences read
+pub async fn replay_file(
+    connection: &AisConnection,
+    path: &Path,
+    pace: Pace,
+) -> anyhow::Result<u64> {
+    let mut lines = open(path).await?.lines();
+    let mut decoder = connection.decoder();
+    let mut clock: Option<(DateTime<Utc>, Instant)> = None; // First timestamp and when we replayed it
+    let mut sentences = 0;
+
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?
+    {
+        let Some((timestamp, sentence)) = split_line(line.trim()) else {
+            continue; // Blank lines and comments
+        };
+
+        if let Pace::Speed(speed) = pace
+            && let Some(timestamp) = timestamp
+        {
+            let (first, started) = *clock.get_or_insert((timestamp, Instant::now()));
+            let offset = (timestamp - first).to_std().unwrap_or_default();
+            time::sleep_until(started + offset.div_f64(speed)).await;
+        }
+
+        let received_at = timestamp.unwrap_or_else(Utc::now);
+        connection
+            .process_line(&mut decoder, sentence, received_at)
+            .await;
+        sentences += 1;
+    }
+
+    Ok(sentences)
+}
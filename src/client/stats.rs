@@ -0,0 +1,227 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    BackingOff, // Waiting to reconnect after a failure
+    Failed,     // Gave up after the policy's maximum attempts
+    Paused,     // Stopped over the API until resumed
+}
+
+#[derive(Clone, Debug, Default)]
+struct Counters {
+    lines: u64,
+    messages: u64,
+    by_type: BTreeMap<u8, u64>,
+    parse_errors: u64,
+    checksum_errors: u64,
+    reconnects: u64,
+}
+
+impl Counters {
+    fn since(&self, earlier: &Counters) -> Counters {
+        Counters {
+            lines: self.lines - earlier.lines,
+            messages: self.messages - earlier.messages,
+            by_type: self
+                .by_type
+                .iter()
+                .map(|(t, n)| (*t, n - earlier.by_type.get(t).unwrap_or(&0)))
+                .filter(|(_, n)| *n > 0)
+                .collect(),
+            parse_errors: self.parse_errors - earlier.parse_errors,
+            checksum_errors: self.checksum_errors - earlier.checksum_errors,
+            reconnects: self.reconnects - earlier.reconnects,
+        }
+    }
+}
+
+struct Inner {
+    state: ConnectionState,
+    state_since: DateTime<Utc>,
+    last_message_at: Option<DateTime<Utc>>,
+    totals: Counters,
+    sampled: Counters, // Totals at the last sample
+    sampled_at: Instant,
+    per_minute: BTreeMap<u8, f64>, // Message rate by type over the last sample interval
+}
+
+// Health and reception counters of one endpoint, updated by its connection task
+pub struct StationStats {
+    inner: Mutex<Inner>,
+}
+
+impl Default for StationStats {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                state: ConnectionState::Connecting,
+                state_since: Utc::now(),
+                last_message_at: None,
+                totals: Counters::default(),
+                sampled: Counters::default(),
+                sampled_at: Instant::now(),
+                per_minute: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl StationStats {
+    pub fn set_state(&self, state: ConnectionState) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state != state {
+            inner.state = state;
+            inner.state_since = Utc::now();
+        }
+    }
+
+    pub fn record_line(&self) {
+        self.inner.lock().unwrap().totals.lines += 1;
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.inner.lock().unwrap().totals.lines
+    }
+
+    pub fn record_message(&self, message_type: u8, received_at: DateTime<Utc>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.totals.messages += 1;
+        *inner.totals.by_type.entry(message_type).or_default() += 1;
+        inner.last_message_at = Some(received_at);
+    }
+
+    pub fn record_parse_error(&self) {
+        self.inner.lock().unwrap().totals.parse_errors += 1;
+    }
+
+    pub fn record_checksum_error(&self) {
+        self.inner.lock().unwrap().totals.checksum_errors += 1;
+    }
+
+    pub fn record_reconnect(&self) {
+        self.inner.lock().unwrap().totals.reconnects += 1;
+    }
+
+    pub fn snapshot(&self, station: &str) -> StationSnapshot {
+        let inner = self.inner.lock().unwrap();
+        StationSnapshot {
+            station: station.to_string(),
+            state: inner.state,
+            state_since: inner.state_since,
+            last_message_at: inner.last_message_at,
+            silent_seconds: inner
+                .last_message_at
+                .map(|at| (Utc::now() - at).num_seconds()),
+            lines: inner.totals.lines,
+            messages: inner.totals.messages,
+            messages_by_type: inner.totals.by_type.clone(),
+            messages_per_minute: inner.per_minute.clone(),
+            parse_errors: inner.totals.parse_errors,
+            checksum_errors: inner.totals.checksum_errors,
+            reconnects: inner.totals.reconnects,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StationSnapshot {
+    pub station: String,
+    pub state: ConnectionState,
+    pub state_since: DateTime<Utc>,
+    pub last_message_at: Option<DateTime<Utc>>,
+    pub silent_seconds: Option<i64>, // Since the last message
+    pub lines: u64,
+    pub messages: u64,
+    pub messages_by_type: BTreeMap<u8, u64>,
+    pub messages_per_minute: BTreeMap<u8, f64>,
+    pub parse_errors: u64,
+    pub checksum_errors: u64,
+    pub reconnects: u64,
+}
+
+// A station's counters over one sample interval, as stored in the time series
+pub struct StationSample {
+    pub station: String,
+    pub state: ConnectionState,
+    pub last_message_at: Option<DateTime<Utc>>,
+    pub messages: u64,
+    pub messages_by_type: BTreeMap<u8, u64>,
+    pub parse_errors: u64,
+    pub checksum_errors: u64,
+    pub reconnects: u64,
+}
+
+// Every endpoint's stats by station name
+#[derive(Default)]
+pub struct StationRegistry {
+    stations: RwLock<BTreeMap<String, Arc<StationStats>>>,
+}
+
+impl StationRegistry {
+    pub fn station(&self, name: &str) -> Arc<StationStats> {
+        if let Some(stats) = self.stations.read().unwrap().get(name) {
+            return stats.clone();
+        }
+        self.stations
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    // Forgets a station that is no longer configured
+    pub fn remove(&self, name: &str) {
+        self.stations.write().unwrap().remove(name);
+    }
+
+    pub fn snapshot(&self) -> Vec<StationSnapshot> {
+        self.stations
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(station, stats)| stats.snapshot(station))
+            .collect()
+    }
+
+    // Counters accumulated since the previous call, one sample per station. Also
+    // refreshes the per-minute rates shown in the snapshot.
+    pub fn sample(&self) -> Vec<StationSample> {
+        let now = Instant::now();
+        self.stations
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(station, stats)| {
+                let mut inner = stats.inner.lock().unwrap();
+                let delta = inner.totals.since(&inner.sampled);
+                let minutes = now.duration_since(inner.sampled_at).as_secs_f64() / 60.0;
+                if minutes > 0.0 {
+                    inner.per_minute = delta
+                        .by_type
+                        .iter()
+                        .map(|(t, n)| (*t, *n as f64 / minutes))
+                        .collect();
+                }
+                inner.sampled = inner.totals.clone();
+                inner.sampled_at = now;
+                StationSample {
+                    station: station.clone(),
+                    state: inner.state,
+                    last_message_at: inner.last_message_at,
+                    messages: delta.messages,
+                    messages_by_type: delta.by_type,
+                    parse_errors: delta.parse_errors,
+                    checksum_errors: delta.checksum_errors,
+                    reconnects: delta.reconnects,
+                }
+            })
+            .collect()
+    }
+}
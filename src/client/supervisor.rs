@@ -0,0 +1,446 @@
+use super::connection;
+use super::envelope::AisEnvelope;
+use super::stats::{ConnectionState, StationRegistry, StationSnapshot};
+use crate::ais::fragments::FragmentStats;
+use crate::config::{self, AisConfig, Endpoint, ReconnectPolicy, StationFile, Transport};
+use crate::server::Feed;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+// How often the config file's modification time is checked
+const RELOAD_POLL: Duration = Duration::from_secs(5);
+
+// Everything a connection task is started with; a change restarts it
+#[derive(PartialEq)]
+struct TaskConfig {
+    endpoint: Endpoint,
+    reconnect: ReconnectPolicy,
+    read_timeout: Duration,
+    fragment_timeout: Duration,
+}
+
+impl TaskConfig {
+    fn new(endpoint: &Endpoint, config: &AisConfig) -> Self {
+        Self {
+            endpoint: endpoint.clone(),
+            reconnect: endpoint.reconnect(config).clone(),
+            read_timeout: endpoint.read_timeout(config),
+            fragment_timeout: config.fragment_timeout,
+        }
+    }
+}
+
+// Where a station came from. Reloading the config file only touches its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    File,
+    Api, // Added at runtime; gone after a restart
+}
+
+struct Task {
+    config: TaskConfig,
+    origin: Origin,
+    handle: Option<JoinHandle<()>>, // None while paused
+}
+
+impl Task {
+    // Aborts the connection task; it has let go of its socket or port once the
+    // returned handle resolves
+    fn stop(&mut self) -> Option<JoinHandle<()>> {
+        let handle = self.handle.take()?;
+        handle.abort();
+        Some(handle)
+    }
+}
+
+// Waits for aborted tasks to wind down, so a replacement can bind the same
+// UDP port or open the same serial device
+async fn finished(handles: impl IntoIterator<Item = JoinHandle<()>>) {
+    for handle in handles {
+        let _ = handle.await; // Cancelled, as intended
+    }
+}
+
+// SIGHUP where the platform has it; elsewhere the config file is only polled
+struct Hangup(#[cfg(unix)] Option<tokio::signal::unix::Signal>);
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            match signal(SignalKind::hangup()) {
+                Ok(hangup) => Hangup(Some(hangup)),
+                Err(e) => {
+                    eprintln!("Can't reload on SIGHUP: {}", e);
+                    Hangup(None)
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        Hangup()
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(hangup) = self.0.as_mut()
+            && hangup.recv().await.is_some()
+        {
+            return;
+        }
+        std::future::pending().await
+    }
+}
+
+// A station's settings and live reception, as reported by the management API
+#[derive(Serialize)]
+pub struct StationStatus {
+    pub address: String,
+    #[serde(flatten)]
+    pub transport: Transport,
+    pub origin: Origin,
+    #[serde(flatten)]
+    pub stats: StationSnapshot,
+}
+
+#[derive(Debug)]
+pub enum StationError {
+    Unknown(String),
+    Exists(String),
+    Invalid(String),
+}
+
+impl fmt::Display for StationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StationError::Unknown(station) => write!(f, "Unknown station {}", station),
+            StationError::Exists(station) => write!(f, "Station {} already exists", station),
+            StationError::Invalid(problem) => write!(f, "Invalid station: {}", problem),
+        }
+    }
+}
+
+impl std::error::Error for StationError {}
+
+struct Inner {
+    config: Arc<AisConfig>,
+    tasks: BTreeMap<String, Task>, // By station name
+    // Stations from the file removed over the API; not started again while the
+    // file still lists them
+    removed: BTreeSet<String>,
+}
+
+// Owns one connection task per station. Follows changes to the config file, and
+// lets the API add, pause, resume and remove stations while running. Stations
+// whose settings didn't change keep their connection, and with it any
+// half-received multi-sentence messages.
+pub struct Supervisor {
+    fragment_stats: Arc<FragmentStats>,
+    raw_feed: Feed,
+    stations: Arc<StationRegistry>,
+    tx: Sender<AisEnvelope>,
+    inner: Mutex<Inner>,
+    // Held for the whole of a change, while stopped tasks are waited for, so
+    // changes don't interleave. `inner` is only held in between.
+    changes: tokio::sync::Mutex<()>,
+}
+
+impl Supervisor {
+    pub async fn new(
+        config: Arc<AisConfig>,
+        fragment_stats: Arc<FragmentStats>,
+        raw_feed: Feed,
+        stations: Arc<StationRegistry>,
+        tx: Sender<AisEnvelope>,
+    ) -> Self {
+        let supervisor = Self {
+            fragment_stats,
+            raw_feed,
+            stations,
+            tx,
+            inner: Mutex::new(Inner {
+                config: config.clone(),
+                tasks: BTreeMap::new(),
+                removed: BTreeSet::new(),
+            }),
+            changes: tokio::sync::Mutex::new(()),
+        };
+        supervisor.apply(config).await;
+        supervisor
+    }
+
+    fn spawn(&self, endpoint: Endpoint, config: Arc<AisConfig>) -> JoinHandle<()> {
+        let stats = self.stations.station(&endpoint.station);
+        tokio::spawn(connection::keep_connected(
+            endpoint,
+            config,
+            self.fragment_stats.clone(),
+            self.raw_feed.clone(),
+            stats,
+            self.tx.clone(),
+        ))
+    }
+
+    // Brings the stations from the file in line with `config`: stops removed ones,
+    // restarts changed ones and starts new ones. A station that gave up after its
+    // maximum attempts is started again; a paused one stays paused. A station added
+    // over the API is taken over if the file now has one by the same name.
+    async fn apply(&self, config: Arc<AisConfig>) {
+        let _change = self.changes.lock().await;
+        let mut stopped = Vec::new();
+        let mut start = Vec::new();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let inner = &mut *inner;
+            let wanted: BTreeMap<&str, TaskConfig> = config
+                .endpoints
+                .iter()
+                .map(|endpoint| {
+                    (
+                        endpoint.station.as_str(),
+                        TaskConfig::new(endpoint, &config),
+                    )
+                })
+                .collect();
+
+            inner.tasks.retain(|station, task| {
+                if task.origin == Origin::Api || wanted.contains_key(station.as_str()) {
+                    return true;
+                }
+                println!("Stopping {}, it is no longer configured", station);
+                stopped.extend(task.stop());
+                self.stations.remove(station);
+                false
+            });
+            // Once the file drops a removed station, listing it again starts it
+            inner
+                .removed
+                .retain(|station| wanted.contains_key(station.as_str()));
+
+            for (station, task_config) in wanted {
+                if inner.removed.contains(station) {
+                    continue;
+                }
+                let Some(task) = inner.tasks.get_mut(station) else {
+                    inner.tasks.insert(
+                        station.to_string(),
+                        Task {
+                            config: task_config,
+                            origin: Origin::File,
+                            handle: None,
+                        },
+                    );
+                    start.push(station.to_string());
+                    continue;
+                };
+                task.origin = Origin::File;
+                let running = task.handle.as_ref().is_some_and(|h| !h.is_finished());
+                if task.config == task_config && (running || task.handle.is_none()) {
+                    continue;
+                }
+                task.config = task_config;
+                if let Some(handle) = task.stop() {
+                    println!("Restarting {}", station);
+                    stopped.push(handle);
+                    start.push(station.to_string());
+                }
+            }
+
+            inner.config = config.clone();
+        }
+
+        finished(stopped).await;
+
+        let mut inner = self.inner.lock().unwrap();
+        for station in start {
+            if let Some(task) = inner.tasks.get_mut(&station) {
+                task.handle = Some(self.spawn(task.config.endpoint.clone(), config.clone()));
+            }
+        }
+    }
+
+    // Reloads on SIGHUP or when the config file is saved. A file that doesn't load
+    // or validate is reported and the running stations are left alone.
+    pub async fn watch(self: Arc<Self>) {
+        let path = config::path();
+        let mut last_modified = modified(&path);
+        let mut hangup = Hangup::new();
+        let mut poll = time::interval(RELOAD_POLL);
+
+        loop {
+            tokio::select! {
+                () = hangup.recv() => {
+                    println!("SIGHUP, reloading {}", path.display());
+                }
+                _ = poll.tick() => {
+                    if modified(&path) == last_modified {
+                        continue;
+                    }
+                    println!("{} changed, reloading", path.display());
+                }
+            }
+            // After a SIGHUP too, so the next poll doesn't load the same file again
+            last_modified = modified(&path);
+            self.reload().await;
+        }
+    }
+
+    async fn reload(&self) {
+        // A file without stations is applied too: it stops every station it started
+        let settings = match config::load() {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Reload failed, keeping the running configuration: {:#}", e);
+                return;
+            }
+        };
+        let running = self.inner.lock().unwrap().config.clone();
+        if needs_restart(&running, &settings.ais) {
+            println!("Only station and connection settings are reloaded; restart for the rest");
+        }
+        self.apply(Arc::new(settings.ais)).await;
+    }
+
+    fn status(&self, station: &str, task: &Task) -> StationStatus {
+        let mut stats = self.stations.station(station).snapshot(station);
+        if task.handle.is_none() {
+            stats.state = ConnectionState::Paused; // Whatever the aborted task last set
+        }
+        StationStatus {
+            address: task.config.endpoint.address.clone(),
+            transport: task.config.endpoint.transport.clone(),
+            origin: task.origin,
+            stats,
+        }
+    }
+
+    pub fn list(&self) -> Vec<StationStatus> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .tasks
+            .iter()
+            .map(|(station, task)| self.status(station, task))
+            .collect()
+    }
+
+    // Starts a station that isn't in the config file, until the next restart
+    pub async fn add(&self, station: StationFile) -> Result<StationStatus, StationError> {
+        let _change = self.changes.lock().await;
+        let mut inner = self.inner.lock().unwrap();
+        let endpoint = config::station(station, &inner.config)
+            .map_err(|e| StationError::Invalid(format!("{:#}", e)))?;
+        if inner.tasks.contains_key(&endpoint.station) {
+            return Err(StationError::Exists(endpoint.station));
+        }
+        println!("Adding {} ({})", endpoint.station, endpoint.address);
+        // Adding back a removed file station hands it to the file again on reload
+        inner.removed.remove(&endpoint.station);
+        let task = Task {
+            config: TaskConfig::new(&endpoint, &inner.config),
+            origin: Origin::Api,
+            handle: Some(self.spawn(endpoint.clone(), inner.config.clone())),
+        };
+        let status = self.status(&endpoint.station, &task);
+        inner.tasks.insert(endpoint.station, task);
+        Ok(status)
+    }
+
+    // Disconnects a station, e.g. during receiver maintenance, and keeps it
+    // disconnected until resumed. Its statistics are kept. Returns once the
+    // connection is closed.
+    pub async fn pause(&self, station: &str) -> Result<StationStatus, StationError> {
+        let _change = self.changes.lock().await;
+        let stopped = self
+            .inner
+            .lock()
+            .unwrap()
+            .tasks
+            .get_mut(station)
+            .ok_or_else(|| StationError::Unknown(station.to_string()))?
+            .stop();
+        if let Some(handle) = stopped {
+            println!("Pausing {}", station);
+            finished([handle]).await;
+            self.stations
+                .station(station)
+                .set_state(ConnectionState::Paused);
+        }
+        let inner = self.inner.lock().unwrap();
+        let task = inner
+            .tasks
+            .get(station)
+            .ok_or_else(|| StationError::Unknown(station.to_string()))?;
+        Ok(self.status(station, task))
+    }
+
+    pub async fn resume(&self, station: &str) -> Result<StationStatus, StationError> {
+        let _change = self.changes.lock().await;
+        let mut inner = self.inner.lock().unwrap();
+        let config = inner.config.clone();
+        let task = inner
+            .tasks
+            .get_mut(station)
+            .ok_or_else(|| StationError::Unknown(station.to_string()))?;
+        if task.handle.is_none() {
+            println!("Resuming {}", station);
+            self.stations
+                .station(station)
+                .set_state(ConnectionState::Connecting);
+            task.handle = Some(self.spawn(task.config.endpoint.clone(), config));
+        }
+        Ok(self.status(station, task))
+    }
+
+    // Stops and forgets a station. One from the config file stays gone until the
+    // file stops listing it; listing it again after that starts it again.
+    pub async fn remove(&self, station: &str) -> Result<(), StationError> {
+        let _change = self.changes.lock().await;
+        let stopped = {
+            let mut inner = self.inner.lock().unwrap();
+            let mut task = inner
+                .tasks
+                .remove(station)
+                .ok_or_else(|| StationError::Unknown(station.to_string()))?;
+            if task.origin == Origin::File {
+                inner.removed.insert(station.to_string());
+            }
+            task.stop()
+        };
+        println!("Removing {}", station);
+        finished(stopped).await;
+        self.stations.remove(station);
+        Ok(())
+    }
+
+    // Stops every connection, on shutdown
+    pub fn stop(&self) {
+        for task in self.inner.lock().unwrap().tasks.values_mut() {
+            task.stop(); // Nothing replaces them, so no need to wait
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Settings the pipeline stages read once at startup
+fn needs_restart(running: &AisConfig, loaded: &AisConfig) -> bool {
+    running.dedup_window != loaded.dedup_window
+        || running.channel_capacity != loaded.channel_capacity
+        || running.batch_size != loaded.batch_size
+        || running.batch_interval != loaded.batch_interval
+        || running.spool != loaded.spool
+        || running.dead_letter != loaded.dead_letter
+        || running.stats_interval != loaded.stats_interval
+        || running.stats_retention != loaded.stats_retention
+        || running.server != loaded.server
+}
@@ -0,0 +1,562 @@
+use super::{
+    AisConfig, ApiConfig, DropPolicy, Endpoint, ReconnectPolicy, ServerConfig, Settings,
+    SpoolConfig, Transport,
+};
+use anyhow::{Context, bail};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Read when AISMAR_CONFIG doesn't name another file
+const DEFAULT_PATH: &str = "aismar.toml";
+
+// Bits 0-5 of the first payload character; 1-27 are defined
+const MESSAGE_TYPES: std::ops::RangeInclusive<u8> = 1..=27;
+
+// Longest wait between reconnect attempts; a station down for longer is still retried daily
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(24 * 3600);
+
+// The config file as written. Everything is optional and falls back to the
+// defaults in config/mod.rs. Durations are in (fractional) seconds.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    database_url: Option<String>,
+    api: ApiFile,
+    ingest: IngestFile,
+    reconnect: ReconnectFile,
+    spool: Option<SpoolFile>,
+    server: Option<ServerFile>,
+    stations: Vec<StationFile>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ApiFile {
+    bind: Option<String>,
+    token: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IngestFile {
+    read_timeout_secs: Option<f64>,
+    dedup_window_secs: Option<f64>,
+    fragment_timeout_secs: Option<f64>,
+    channel_capacity: Option<usize>,
+    batch_size: Option<usize>,
+    batch_interval_secs: Option<f64>,
+    stats_interval_secs: Option<f64>,
+    stats_retention_secs: Option<f64>,
+    dead_letter_path: Option<PathBuf>,
+}
+
+// Global [reconnect], or a station's own table overriding some of it
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ReconnectFile {
+    initial_delay_secs: Option<f64>,
+    max_delay_secs: Option<f64>,
+    multiplier: Option<f64>,
+    jitter: Option<f64>,
+    max_attempts: Option<usize>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SpoolFile {
+    enabled: Option<bool>,
+    path: Option<PathBuf>,
+    max_bytes: Option<u64>,
+    drop_policy: Option<DropPolicy>,
+    retry_interval_secs: Option<f64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerFile {
+    enabled: Option<bool>,
+    raw_address: Option<String>, // "" turns this feed off
+    filtered_address: Option<String>,
+    client_buffer: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum TransportKind {
+    #[default]
+    Tcp,
+    Udp,
+    Serial,
+}
+
+// One [[stations]] entry, also the body for adding a station over the API
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StationFile {
+    name: String,
+    address: String,
+    #[serde(default)]
+    transport: TransportKind,
+    multicast: Option<IpAddr>, // UDP only
+    baud_rate: Option<u32>,    // Serial only, 38400 if not given
+    read_timeout_secs: Option<f64>,
+    reconnect: Option<ReconnectFile>,
+    message_types: Option<BTreeSet<u8>>,
+}
+
+// Everything wrong with the configuration, so one start reports it all
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn check(&mut self, ok: bool, problem: impl FnOnce() -> String) {
+        if !ok {
+            self.0.push(problem());
+        }
+    }
+
+    fn seconds(&mut self, field: &str, secs: Option<f64>, default: Duration) -> Duration {
+        let Some(secs) = secs else {
+            return default;
+        };
+        Duration::try_from_secs_f64(secs).unwrap_or_else(|_| {
+            self.0.push(format!(
+                "{} must be a number of seconds, not {}",
+                field, secs
+            ));
+            default
+        })
+    }
+}
+
+// The file named by AISMAR_CONFIG, or aismar.toml in the working directory
+pub fn path() -> PathBuf {
+    env::var_os("AISMAR_CONFIG").map_or_else(|| PathBuf::from(DEFAULT_PATH), PathBuf::from)
+}
+
+// Reads the config file, which only has to exist if AISMAR_CONFIG names it, applies
+// the environment overrides and validates the result
+pub fn load() -> anyhow::Result<Settings> {
+    let path = path();
+    let required = env::var_os("AISMAR_CONFIG").is_some();
+    let file: ConfigFile = match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text)
+            .with_context(|| format!("Invalid config file {}", path.display()))?,
+        Err(e) if !required && e.kind() == ErrorKind::NotFound => ConfigFile::default(),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read config file {}", path.display()));
+        }
+    };
+
+    let mut problems = Problems::default();
+    let mut settings = file.resolve(&mut problems);
+    apply_env(&mut settings, &mut problems);
+    validate(&settings, &mut problems);
+    if !problems.0.is_empty() {
+        bail!(
+            "Invalid configuration ({}):\n  {}",
+            path.display(),
+            problems.0.join("\n  ")
+        );
+    }
+    Ok(settings)
+}
+
+impl ConfigFile {
+    fn resolve(self, problems: &mut Problems) -> Settings {
+        let defaults = AisConfig::default();
+        let ingest = &self.ingest;
+        let reconnect = self
+            .reconnect
+            .resolve("reconnect", &defaults.reconnect, problems);
+        let spool = match self.spool {
+            Some(spool) => spool.resolve(defaults.spool, problems),
+            None => defaults.spool,
+        };
+        let server = self.server.and_then(ServerFile::resolve);
+        let endpoints = self
+            .stations
+            .into_iter()
+            .map(|station| station.resolve(&reconnect, problems))
+            .collect();
+
+        let ais = AisConfig {
+            endpoints,
+            read_timeout: problems.seconds(
+                "ingest.read_timeout_secs",
+                ingest.read_timeout_secs,
+                defaults.read_timeout,
+            ),
+            dedup_window: problems.seconds(
+                "ingest.dedup_window_secs",
+                ingest.dedup_window_secs,
+                defaults.dedup_window,
+            ),
+            fragment_timeout: problems.seconds(
+                "ingest.fragment_timeout_secs",
+                ingest.fragment_timeout_secs,
+                defaults.fragment_timeout,
+            ),
+            channel_capacity: ingest.channel_capacity.unwrap_or(defaults.channel_capacity),
+            batch_size: ingest.batch_size.unwrap_or(defaults.batch_size),
+            batch_interval: problems.seconds(
+                "ingest.batch_interval_secs",
+                ingest.batch_interval_secs,
+                defaults.batch_interval,
+            ),
+            stats_interval: problems.seconds(
+                "ingest.stats_interval_secs",
+                ingest.stats_interval_secs,
+                defaults.stats_interval,
+            ),
+            stats_retention: problems.seconds(
+                "ingest.stats_retention_secs",
+                ingest.stats_retention_secs,
+                defaults.stats_retention,
+            ),
+            reconnect,
+            spool,
+            dead_letter: self.ingest.dead_letter_path.unwrap_or(defaults.dead_letter),
+            server,
+        };
+        Settings {
+            database_url: self.database_url.unwrap_or_default(),
+            api: ApiConfig {
+                bind: self.api.bind.unwrap_or(ApiConfig::default().bind),
+                token: self.api.token,
+            },
+            ais,
+        }
+    }
+}
+
+impl ReconnectFile {
+    // The settings given here, the rest taken from `base`
+    fn resolve(
+        &self,
+        section: &str,
+        base: &ReconnectPolicy,
+        problems: &mut Problems,
+    ) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: problems.seconds(
+                &format!("{}.initial_delay_secs", section),
+                self.initial_delay_secs,
+                base.initial_delay,
+            ),
+            max_delay: problems.seconds(
+                &format!("{}.max_delay_secs", section),
+                self.max_delay_secs,
+                base.max_delay,
+            ),
+            multiplier: self.multiplier.unwrap_or(base.multiplier),
+            jitter: self.jitter.unwrap_or(base.jitter),
+            max_attempts: self.max_attempts.or(base.max_attempts),
+        }
+    }
+}
+
+impl SpoolFile {
+    fn resolve(self, default: Option<SpoolConfig>, problems: &mut Problems) -> Option<SpoolConfig> {
+        if self.enabled == Some(false) {
+            return None;
+        }
+        let default = default.unwrap_or_default();
+        Some(SpoolConfig {
+            path: self.path.unwrap_or(default.path),
+            max_bytes: self.max_bytes.unwrap_or(default.max_bytes),
+            drop_policy: self.drop_policy.unwrap_or(default.drop_policy),
+            retry_interval: problems.seconds(
+                "spool.retry_interval_secs",
+                self.retry_interval_secs,
+                default.retry_interval,
+            ),
+        })
+    }
+}
+
+impl ServerFile {
+    // A [server] table turns re-broadcasting on unless it says enabled = false
+    fn resolve(self) -> Option<ServerConfig> {
+        if self.enabled == Some(false) {
+            return None;
+        }
+        let default = ServerConfig::default();
+        let address = |address: Option<String>, default: Option<String>| match address {
+            Some(address) if address.is_empty() => None,
+            Some(address) => Some(address),
+            None => default,
+        };
+        Some(ServerConfig {
+            raw_address: address(self.raw_address, default.raw_address),
+            filtered_address: address(self.filtered_address, default.filtered_address),
+            client_buffer: self.client_buffer.unwrap_or(default.client_buffer),
+        })
+    }
+}
+
+impl StationFile {
+    fn resolve(self, reconnect: &ReconnectPolicy, problems: &mut Problems) -> Endpoint {
+        let what = format!("station \"{}\"", self.name);
+        problems.check(
+            self.multicast.is_none() || self.transport == TransportKind::Udp,
+            || format!("{}: multicast is only for UDP stations", what),
+        );
+        problems.check(
+            self.baud_rate.is_none() || self.transport == TransportKind::Serial,
+            || format!("{}: baud_rate is only for serial stations", what),
+        );
+
+        let mut endpoint = match (self.transport, self.multicast) {
+            (TransportKind::Udp, Some(group)) => {
+                Endpoint::udp_multicast(&self.name, &self.address, group)
+            }
+            (TransportKind::Udp, None) => Endpoint::udp(&self.name, &self.address),
+            (TransportKind::Serial, _) => {
+                Endpoint::serial(&self.name, &self.address, self.baud_rate.unwrap_or(38400))
+            }
+            (TransportKind::Tcp, _) => Endpoint::new(&self.name, &self.address),
+        };
+        endpoint.reconnect = self
+            .reconnect
+            .map(|own| own.resolve(&format!("{}: reconnect", what), reconnect, problems));
+        endpoint.read_timeout = self.read_timeout_secs.map(|secs| {
+            problems.seconds(
+                &format!("{}: read_timeout_secs", what),
+                Some(secs),
+                Duration::ZERO,
+            )
+        });
+        endpoint.message_types = self.message_types;
+        endpoint
+    }
+}
+
+// A station added at runtime, checked like one from the file. Settings it leaves
+// out come from `config`.
+pub fn station(station: StationFile, config: &AisConfig) -> anyhow::Result<Endpoint> {
+    let mut problems = Problems::default();
+    let endpoint = station.resolve(&config.reconnect, &mut problems);
+    check_endpoint(
+        &mut problems,
+        &format!("station \"{}\"", endpoint.station),
+        &endpoint,
+    );
+    if !problems.0.is_empty() {
+        bail!("{}", problems.0.join("; "));
+    }
+    Ok(endpoint)
+}
+
+// Station names as they appear in variable names: AISMAR_STATION_VELI_LOSINJ_ADDRESS
+fn env_name(station: &str) -> String {
+    station
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// The environment wins over the file, so a deployment can move things around
+// without editing it
+fn apply_env(settings: &mut Settings, problems: &mut Problems) {
+    if let Ok(url) = env::var("DATABASE_URL") {
+        settings.database_url = url;
+    }
+    if let Ok(bind) = env::var("AISMAR_API_BIND") {
+        settings.api.bind = bind;
+    }
+    if let Ok(token) = env::var("AISMAR_API_TOKEN") {
+        settings.api.token = Some(token);
+    }
+    for endpoint in &mut settings.ais.endpoints {
+        let variable = format!("AISMAR_STATION_{}_ADDRESS", env_name(&endpoint.station));
+        if let Ok(address) = env::var(variable) {
+            endpoint.address = address;
+        }
+    }
+
+    // Field units with the transponder wired straight to the Pi
+    if let Ok(device) = env::var("AISMAR_SERIAL_DEVICE") {
+        let baud_rate = match env::var("AISMAR_SERIAL_BAUD") {
+            Ok(baud) => baud.parse().unwrap_or_else(|_| {
+                problems
+                    .0
+                    .push(format!("AISMAR_SERIAL_BAUD is not a number: {}", baud));
+                38400
+            }),
+            Err(_) => 38400,
+        };
+        settings
+            .ais
+            .endpoints
+            .push(Endpoint::serial("Serial", &device, baud_rate));
+    }
+}
+
+fn validate(settings: &Settings, problems: &mut Problems) {
+    problems.check(!settings.database_url.is_empty(), || {
+        "database_url is not set in the file or DATABASE_URL".into()
+    });
+    check_socket_address(problems, "api.bind", &settings.api.bind);
+    problems.check(
+        settings
+            .api
+            .token
+            .as_ref()
+            .is_none_or(|t| !t.trim().is_empty()),
+        || "api.token is empty; leave it out to turn station management and alarm acks off".into(),
+    );
+
+    let ais = &settings.ais;
+    for (field, duration) in [
+        ("ingest.read_timeout_secs", ais.read_timeout),
+        ("ingest.dedup_window_secs", ais.dedup_window),
+        ("ingest.fragment_timeout_secs", ais.fragment_timeout),
+        ("ingest.batch_interval_secs", ais.batch_interval),
+        ("ingest.stats_interval_secs", ais.stats_interval),
+        ("ingest.stats_retention_secs", ais.stats_retention),
+    ] {
+        problems.check(!duration.is_zero(), || {
+            format!("{} must be above zero", field)
+        });
+    }
+    problems.check(ais.channel_capacity > 0, || {
+        "ingest.channel_capacity must be above zero".into()
+    });
+    problems.check(ais.batch_size > 0, || {
+        "ingest.batch_size must be above zero".into()
+    });
+    check_reconnect(problems, "reconnect", &ais.reconnect);
+
+    if let Some(spool) = &ais.spool {
+        problems.check(spool.max_bytes > 0, || {
+            "spool.max_bytes must be above zero".into()
+        });
+        problems.check(!spool.retry_interval.is_zero(), || {
+            "spool.retry_interval_secs must be above zero".into()
+        });
+    }
+
+    if let Some(server) = &ais.server {
+        for (field, address) in [
+            ("server.raw_address", &server.raw_address),
+            ("server.filtered_address", &server.filtered_address),
+        ] {
+            if let Some(address) = address {
+                check_socket_address(problems, field, address);
+            }
+        }
+        problems.check(server.client_buffer > 0, || {
+            "server.client_buffer must be above zero".into()
+        });
+    }
+
+    let mut names = HashSet::new();
+    for endpoint in &ais.endpoints {
+        let what = format!("station \"{}\"", endpoint.station);
+        problems.check(names.insert(&endpoint.station), || {
+            format!("{} is configured more than once", what)
+        });
+        check_endpoint(problems, &what, endpoint);
+    }
+}
+
+fn check_socket_address(problems: &mut Problems, field: &str, address: &str) {
+    problems.check(address.parse::<SocketAddr>().is_ok(), || {
+        format!("{} is not an IP address and port: {}", field, address)
+    });
+}
+
+fn check_endpoint(problems: &mut Problems, what: &str, endpoint: &Endpoint) {
+    problems.check(!endpoint.station.trim().is_empty(), || {
+        "Every station needs a name".into()
+    });
+    if let Some(policy) = &endpoint.reconnect {
+        check_reconnect(problems, &format!("{}: reconnect", what), policy);
+    }
+    if let Some(timeout) = endpoint.read_timeout {
+        problems.check(!timeout.is_zero(), || {
+            format!("{}: read_timeout_secs must be above zero", what)
+        });
+    }
+    if let Some(types) = &endpoint.message_types {
+        problems.check(!types.is_empty(), || {
+            format!("{}: message_types is empty, nothing would be stored", what)
+        });
+        for message_type in types.iter().filter(|t| !MESSAGE_TYPES.contains(t)) {
+            problems.0.push(format!(
+                "{}: there is no message type {}",
+                what, message_type
+            ));
+        }
+    }
+
+    match &endpoint.transport {
+        Transport::Tcp => {
+            // Host names are fine here, they're resolved on every connect
+            let valid = endpoint
+                .address
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            problems.check(valid, || {
+                format!(
+                    "{}: address must be host:port, not {}",
+                    what, endpoint.address
+                )
+            });
+        }
+        Transport::Udp { multicast } => {
+            check_socket_address(problems, &format!("{}: address", what), &endpoint.address);
+            if let Some(group) = multicast {
+                problems.check(group.is_multicast(), || {
+                    format!("{}: {} is not a multicast group", what, group)
+                });
+            }
+        }
+        Transport::Serial { baud_rate } => {
+            problems.check(!endpoint.address.is_empty(), || {
+                format!("{}: address must name the serial device", what)
+            });
+            problems.check(*baud_rate > 0, || {
+                format!("{}: baud_rate must be above zero", what)
+            });
+        }
+    }
+}
+
+fn check_reconnect(problems: &mut Problems, section: &str, policy: &ReconnectPolicy) {
+    problems.check(!policy.initial_delay.is_zero(), || {
+        format!("{}.initial_delay_secs must be above zero", section)
+    });
+    problems.check(policy.max_delay >= policy.initial_delay, || {
+        format!(
+            "{}.max_delay_secs is shorter than initial_delay_secs",
+            section
+        )
+    });
+    problems.check(policy.max_delay <= MAX_RECONNECT_DELAY, || {
+        format!(
+            "{}.max_delay_secs must be at most {}",
+            section,
+            MAX_RECONNECT_DELAY.as_secs()
+        )
+    });
+    problems.check(policy.multiplier >= 1.0, || {
+        format!("{}.multiplier must be at least 1", section)
+    });
+    problems.check((0.0..=1.0).contains(&policy.jitter), || {
+        format!("{}.jitter must be between 0 and 1", section)
+    });
+}
@@ -0,0 +1,214 @@
+mod file;
+
+pub use file::{StationFile, load, path, station};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// How NMEA reaches us from an endpoint
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "transport", rename_all = "lowercase")]
+pub enum Transport {
+    Tcp, // We dial out to `address`
+    Udp {
+        multicast: Option<IpAddr>, // Group to join; None for plain unicast datagrams
+    }, // Receivers send datagrams to `address`, which we bind
+    Serial {
+        baud_rate: u32,
+    }, // `address` is the device, e.g. /dev/ttyUSB0
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    pub station: String, // Station name recorded with every message from this endpoint
+    pub address: String,
+    pub transport: Transport,
+    pub reconnect: Option<ReconnectPolicy>, // None uses AisConfig::reconnect
+    pub read_timeout: Option<Duration>,     // None uses AisConfig::read_timeout
+    pub message_types: Option<BTreeSet<u8>>, // Only these are stored; None stores every type
+}
+
+impl Endpoint {
+    pub fn new(station: &str, address: &str) -> Self {
+        Self {
+            station: station.into(),
+            address: address.into(),
+            transport: Transport::Tcp,
+            reconnect: None,
+            read_timeout: None,
+            message_types: None,
+        }
+    }
+
+    pub fn reconnect<'a>(&'a self, config: &'a AisConfig) -> &'a ReconnectPolicy {
+        self.reconnect.as_ref().unwrap_or(&config.reconnect)
+    }
+
+    pub fn read_timeout(&self, config: &AisConfig) -> Duration {
+        self.read_timeout.unwrap_or(config.read_timeout)
+    }
+
+    pub fn accepts(&self, message_type: u8) -> bool {
+        self.message_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&message_type))
+    }
+
+    // Listens for datagrams on a local address, e.g. "0.0.0.0:10110"
+    pub fn udp(station: &str, bind: &str) -> Self {
+        Self {
+            transport: Transport::Udp { multicast: None },
+            ..Self::new(station, bind)
+        }
+    }
+
+    pub fn serial(station: &str, device: &str, baud_rate: u32) -> Self {
+        Self {
+            transport: Transport::Serial { baud_rate },
+            ..Self::new(station, device)
+        }
+    }
+
+    // For receivers that publish to a group instead of one host
+    pub fn udp_multicast(station: &str, bind: &str, group: IpAddr) -> Self {
+        Self {
+            transport: Transport::Udp {
+                multicast: Some(group),
+            },
+            ..Self::new(station, bind)
+        }
+    }
+}
+
+// What to throw away once the spool reaches its size limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    DropOldest,
+    DropNewest,
+}
+
+// Messages that can't be written while the database is down are kept on disk here
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpoolConfig {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub drop_policy: DropPolicy,
+    pub retry_interval: Duration, // How long to spool before trying the database again
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("ais_spool.jsonl"),
+            max_bytes: 256 * 1024 * 1024,
+            drop_policy: DropPolicy::DropOldest,
+            retry_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+// How long to wait between attempts to reach an endpoint. The delay starts at
+// `initial_delay` and grows by `multiplier` after every failure up to `max_delay`;
+// `jitter` spreads it by up to that fraction either way so stations don't retry in step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub max_attempts: Option<usize>, // None keeps trying forever
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(300),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+// Re-broadcasts the merged stream to downstream clients such as OpenCPN
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerConfig {
+    pub raw_address: Option<String>, // Every line as received from every endpoint
+    pub filtered_address: Option<String>, // Each decoded message once, after deduplication
+    pub client_buffer: usize,        // Lines a slow client may fall behind before it skips some
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            raw_address: Some("0.0.0.0:10110".into()),
+            filtered_address: Some("0.0.0.0:10111".into()),
+            client_buffer: 1_000,
+        }
+    }
+}
+
+// The HTTP API
+#[derive(Clone, Debug)]
+pub struct ApiConfig {
+    pub bind: String,
+    pub token: Option<String>, // Bearer token for station management and alarm acks; None turns them off
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:3000".into(),
+            token: None,
+        }
+    }
+}
+
+// Everything read at startup: the config file with environment overrides applied
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub database_url: String,
+    pub api: ApiConfig,
+    pub ais: AisConfig,
+}
+
+#[derive(Clone, Debug)]
+pub struct AisConfig {
+    pub endpoints: Vec<Endpoint>, // Multiple endpoints to connect to
+    pub reconnect: ReconnectPolicy,
+    pub read_timeout: Duration,
+    pub dedup_window: Duration, // Copies of a payload within this window are stored once
+    pub fragment_timeout: Duration, // Multi-sentence messages not completed within this are dropped
+    pub channel_capacity: usize, // Messages buffered between the connections and the writer
+    pub batch_size: usize,      // Rows written per database round-trip at most
+    pub batch_interval: Duration, // Flush a partial batch after this long
+    pub spool: Option<SpoolConfig>, // None drops failed batches as before
+    pub dead_letter: PathBuf,   // Messages the database rejects are kept here
+    pub stats_interval: Duration, // How often station statistics are written to the database
+    pub stats_retention: Duration, // Station statistics older than this are deleted
+    pub server: Option<ServerConfig>, // None doesn't re-broadcast
+}
+
+impl Default for AisConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(), // Stations come from the config file
+            reconnect: ReconnectPolicy::default(),
+            read_timeout: Duration::from_secs(30),
+            dedup_window: Duration::from_secs(2),
+            fragment_timeout: Duration::from_secs(5),
+            channel_capacity: 10_000,
+            batch_size: 500,
+            batch_interval: Duration::from_secs(1),
+            spool: Some(SpoolConfig::default()),
+            dead_letter: PathBuf::from("ais_dead_letter.jsonl"),
+            stats_interval: Duration::from_secs(60),
+            stats_retention: Duration::from_secs(30 * 24 * 3600),
+            server: None,
+        }
+    }
+}
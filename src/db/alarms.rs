@@ -0,0 +1,189 @@
+use super::database::column;
+use crate::alarms::{Alarm, AlarmEngine, AlarmKind, AlarmState, CLEARED_HISTORY};
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time;
+
+// How long to wait before trying to load the saved alarms again
+const LOAD_RETRY: Duration = Duration::from_secs(5);
+
+fn kind(name: &str) -> Option<AlarmKind> {
+    match name {
+        "LightFailure" => Some(AlarmKind::LightFailure),
+        "RaconFault" => Some(AlarmKind::RaconFault),
+        "HealthAlarm" => Some(AlarmKind::HealthAlarm),
+        "OffPosition" => Some(AlarmKind::OffPosition),
+        _ => None,
+    }
+}
+
+fn state(name: &str) -> Option<AlarmState> {
+    match name {
+        "Raised" => Some(AlarmState::Raised),
+        "Acknowledged" => Some(AlarmState::Acknowledged),
+        "Cleared" => Some(AlarmState::Cleared),
+        _ => None,
+    }
+}
+
+// The open alarms and the most recently cleared ones, as the last run left them
+pub async fn load_alarms(pool: &PgPool) -> Result<Vec<Alarm>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "(SELECT * FROM aton_alarms WHERE state <> 'Cleared')
+         UNION ALL
+         (SELECT * FROM aton_alarms WHERE state = 'Cleared' ORDER BY id DESC LIMIT $1)",
+        CLEARED_HISTORY as i64
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut alarms = Vec::new();
+    for row in rows {
+        let (Some(id), Some(mmsi), Some(name), Some(kind_name), Some(state_name), Some(station)) =
+            (row.id, row.mmsi, row.name, row.kind, row.state, row.station)
+        else {
+            continue;
+        };
+        let (Some(kind), Some(state)) = (kind(&kind_name), state(&state_name)) else {
+            eprintln!(
+                "Skipping saved alarm #{}: unknown {} {}",
+                id, kind_name, state_name
+            );
+            continue;
+        };
+        let (Some(raised_at), Some(last_reported_at)) = (row.raised_at, row.last_reported_at)
+        else {
+            continue;
+        };
+        alarms.push(Alarm {
+            id: id as u64,
+            mmsi: mmsi as u32,
+            name,
+            kind,
+            state,
+            station,
+            raised_at: raised_at.and_utc(),
+            last_reported_at: last_reported_at.and_utc(),
+            acknowledged_at: row.acknowledged_at.map(|t| t.and_utc()),
+            cleared_at: row.cleared_at.map(|t| t.and_utc()),
+            reports: row.reports.unwrap_or_default() as u64,
+        });
+    }
+    Ok(alarms)
+}
+
+// Adds alarms raised since the last save
+async fn insert_alarms(pool: &PgPool, alarms: &[&Alarm]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO aton_alarms
+            (id, mmsi, name, kind, state, station, raised_at, last_reported_at,
+             acknowledged_at, cleared_at, reports)
+        SELECT * FROM UNNEST($1::int8[], $2::int8[], $3::text[], $4::text[], $5::text[],
+                             $6::text[], $7::timestamp[], $8::timestamp[],
+                             $9::timestamp[], $10::timestamp[], $11::int8[])",
+        &column(alarms, |a| a.id as i64),
+        &column(alarms, |a| a.mmsi as i64),
+        &column(alarms, |a| a.name.clone()),
+        &column(alarms, |a| format!("{:?}", a.kind)),
+        &column(alarms, |a| format!("{:?}", a.state)),
+        &column(alarms, |a| a.station.clone()),
+        &column(alarms, |a| a.raised_at.naive_utc()),
+        &column(alarms, |a| a.last_reported_at.naive_utc()),
+        &column(alarms, |a| a.acknowledged_at.map(|t| t.naive_utc())) as &[Option<NaiveDateTime>],
+        &column(alarms, |a| a.cleared_at.map(|t| t.naive_utc())) as &[Option<NaiveDateTime>],
+        &column(alarms, |a| a.reports as i64),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Writes the latest state of alarms that are already saved
+async fn update_alarms(pool: &PgPool, alarms: &[&Alarm]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE aton_alarms SET
+            state = t.state,
+            last_reported_at = t.last_reported_at,
+            acknowledged_at = t.acknowledged_at,
+            cleared_at = t.cleared_at,
+            reports = t.reports
+        FROM UNNEST($1::int8[], $2::text[], $3::timestamp[], $4::timestamp[],
+                    $5::timestamp[], $6::int8[])
+            AS t(id, state, last_reported_at, acknowledged_at, cleared_at, reports)
+        WHERE aton_alarms.id = t.id",
+        &column(alarms, |a| a.id as i64),
+        &column(alarms, |a| format!("{:?}", a.state)),
+        &column(alarms, |a| a.last_reported_at.naive_utc()),
+        &column(alarms, |a| a.acknowledged_at.map(|t| t.naive_utc())) as &[Option<NaiveDateTime>],
+        &column(alarms, |a| a.cleared_at.map(|t| t.naive_utc())) as &[Option<NaiveDateTime>],
+        &column(alarms, |a| a.reports as i64),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Loads the alarms of the last run into `engine`, retrying until the database
+// answers, then saves alarm changes as the engine makes them. Changes arriving
+// together are written at once, each alarm in its latest state.
+pub async fn run(pool: Arc<PgPool>, engine: Arc<AlarmEngine>, mut rx: UnboundedReceiver<Alarm>) {
+    // Open alarms that have a row, to be updated rather than inserted
+    let mut saved = BTreeSet::new();
+    loop {
+        match load_alarms(&pool).await {
+            Ok(alarms) => {
+                saved.extend(
+                    alarms
+                        .iter()
+                        .filter(|a| a.state != AlarmState::Cleared)
+                        .map(|a| a.id),
+                );
+                engine.restore(alarms);
+                break;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to load saved alarms, not checking AtoN reports yet: {}",
+                    e
+                );
+                time::sleep(LOAD_RETRY).await;
+            }
+        }
+    }
+
+    while let Some(alarm) = rx.recv().await {
+        let mut changed = BTreeMap::from([(alarm.id, alarm)]);
+        while let Ok(alarm) = rx.try_recv() {
+            changed.insert(alarm.id, alarm);
+        }
+        let (updated, raised): (Vec<_>, Vec<_>) =
+            changed.values().partition(|a| saved.contains(&a.id));
+        // One that failed to go in is tried again with its next change
+        if !raised.is_empty() {
+            match insert_alarms(&pool, &raised).await {
+                Ok(()) => saved.extend(
+                    raised
+                        .iter()
+                        .filter(|a| a.state != AlarmState::Cleared)
+                        .map(|a| a.id),
+                ),
+                Err(e) => eprintln!("Failed to save {} new alarms: {}", raised.len(), e),
+            }
+        }
+        if !updated.is_empty() {
+            match update_alarms(&pool, &updated).await {
+                // A cleared alarm doesn't change again
+                Ok(()) => {
+                    for alarm in updated.iter().filter(|a| a.state == AlarmState::Cleared) {
+                        saved.remove(&alarm.id);
+                    }
+                }
+                Err(e) => eprintln!("Failed to save {} alarms: {}", updated.len(), e),
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use super::database::{AtonRow, column, enum_name};
+use crate::ais::msg21::MonitoredStatus;
+use crate::ais::validation::validate_position;
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use std::collections::BTreeMap;
+
+// Keeps `aton_status` at the latest report of every aid; the table's trigger
+// writes a row to `aton_status_history` whenever the status changes
+pub async fn upsert_aton_status(
+    conn: &mut PgConnection,
+    rows: &[AtonRow],
+) -> Result<(), sqlx::Error> {
+    // One upsert can't touch the same row twice, so keep only the newest report per aid.
+    // Any page but 7 carries no light, RACON or health we decode, so those come from the
+    // newest report in the batch that had them.
+    let mut latest: BTreeMap<u32, &AtonRow> = BTreeMap::new();
+    let mut monitored: BTreeMap<u32, (DateTime<Utc>, MonitoredStatus)> = BTreeMap::new();
+    for row in rows {
+        let newer = latest
+            .get(&row.1.mmsi)
+            .is_none_or(|(seen, _, _)| seen.received_at <= row.0.received_at);
+        if newer {
+            latest.insert(row.1.mmsi, row);
+        }
+        if let Some(status) = row.2.and_then(|p| p.monitored()) {
+            let newer = monitored
+                .get(&row.1.mmsi)
+                .is_none_or(|(seen, _)| *seen <= row.0.received_at);
+            if newer {
+                monitored.insert(row.1.mmsi, (row.0.received_at, status));
+            }
+        }
+    }
+
+    if latest.is_empty() {
+        return Ok(());
+    }
+    let latest: Vec<_> = latest.into_values().collect();
+    let position = column(&latest, |(_, aton, _)| {
+        validate_position(aton.latitude, aton.longitude)
+    });
+    // None if only other pages came in, which leave the stored status as it was
+    let status = column(&latest, |(_, aton, _)| {
+        monitored.get(&aton.mmsi).map(|(_, status)| *status)
+    });
+    let received_at = column(&latest, |(source, _, _)| source.received_at.naive_utc());
+    // Reports replayed from the spool must not overwrite a newer status
+    sqlx::query!(
+        "INSERT INTO aton_status
+            (mmsi, name, aid_type, latitude, longitude, off_position, virtual_aid, status_page,
+             racon_status, light_status, health_status, first_seen, last_seen)
+        SELECT mmsi, name, aid_type, latitude, longitude, off_position, virtual_aid, status_page,
+               racon_status, light_status, health_status, received_at, received_at
+        FROM UNNEST($1::int8[], $2::text[], $3::text[], $4::float8[], $5::float8[], $6::bool[],
+                    $7::bool[], $8::int4[], $9::text[], $10::text[], $11::text[], $12::timestamp[])
+            AS t(mmsi, name, aid_type, latitude, longitude, off_position, virtual_aid, status_page,
+                 racon_status, light_status, health_status, received_at)
+        ON CONFLICT (mmsi) DO UPDATE SET
+            name = EXCLUDED.name,
+            aid_type = EXCLUDED.aid_type,
+            latitude = COALESCE(EXCLUDED.latitude, aton_status.latitude),
+            longitude = COALESCE(EXCLUDED.longitude, aton_status.longitude),
+            off_position = EXCLUDED.off_position,
+            virtual_aid = EXCLUDED.virtual_aid,
+            status_page = COALESCE(EXCLUDED.status_page, aton_status.status_page),
+            racon_status = COALESCE(EXCLUDED.racon_status, aton_status.racon_status),
+            light_status = COALESCE(EXCLUDED.light_status, aton_status.light_status),
+            health_status = COALESCE(EXCLUDED.health_status, aton_status.health_status),
+            last_seen = EXCLUDED.last_seen
+          WHERE EXCLUDED.last_seen >= aton_status.last_seen",
+        &column(&latest, |(_, aton, _)| aton.mmsi as i64),
+        &column(&latest, |(_, aton, _)| aton.name.to_string()),
+        &column(&latest, |(_, aton, _)| enum_name(aton.aid_type.as_ref())) as &[Option<String>],
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(&latest, |(_, aton, _)| aton.off_position),
+        &column(&latest, |(_, aton, _)| aton.virtual_aid),
+        &column(&latest, |(_, _, page)| page.map(|p| p.page_id() as i32)) as &[Option<i32>],
+        &column(&status, |s| enum_name(s.map(|s| s.racon))) as &[Option<String>],
+        &column(&status, |s| enum_name(s.map(|s| s.light))) as &[Option<String>],
+        &column(&status, |s| enum_name(s.map(|s| s.health))) as &[Option<String>],
+        &received_at,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
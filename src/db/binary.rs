@@ -0,0 +1,255 @@
+use super::database::{SourceColumns, column, enum_name};
+use crate::ais::binary::{
+    ApplicationData, AreaNotice, AtonMonitoring, BinaryMessage, MetHydro, application_name,
+};
+use crate::ais::validation::validate_position;
+use crate::client::envelope::MessageSource;
+use chrono::NaiveDateTime;
+use serde_json::json;
+use sqlx::PgConnection;
+
+// Writes every binary message, then the decoded application data to its own table
+pub async fn insert_binary_messages(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, BinaryMessage)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_binary_messages
+            (message_type, mmsi, dest_mmsi, dac, fi, application, data,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, dest_mmsi, dac, fi, application, data, station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::int8[], $4::int4[], $5::int4[], $6::text[],
+                    $7::bytea[],
+                    $8::text[], $9::timestamp[], $10::jsonb[], $11::text[], $12::timestamp[])
+            AS t(message_type, mmsi, dest_mmsi, dac, fi, application, data,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, bm)| bm.message_type as i32),
+        &column(rows, |(_, bm)| bm.mmsi as i64),
+        &column(rows, |(_, bm)| bm.dest_mmsi.map(i64::from)) as &[Option<i64>],
+        &column(rows, |(_, bm)| bm.dac as i32),
+        &column(rows, |(_, bm)| bm.fi as i32),
+        &column(rows, |(_, bm)| application_name(bm.dac, bm.fi)
+            .map(str::to_string)) as &[Option<String>],
+        &column(rows, |(_, bm)| bm.data.clone()),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let mut met_hydro = Vec::new();
+    let mut area_notices = Vec::new();
+    let mut aton_monitoring = Vec::new();
+    for (source, bm) in rows {
+        match &bm.application {
+            Some(ApplicationData::MetHydro(mh)) => met_hydro.push((source, bm, mh)),
+            Some(ApplicationData::AreaNotice(an)) => area_notices.push((source, bm, an)),
+            Some(ApplicationData::AtonMonitoring(am)) => aton_monitoring.push((source, bm, am)),
+            None => {}
+        }
+    }
+    insert_met_hydro(conn, &met_hydro).await?;
+    insert_area_notices(conn, &area_notices).await?;
+    insert_aton_monitoring(conn, &aton_monitoring).await?;
+    Ok(())
+}
+
+async fn insert_met_hydro(
+    conn: &mut PgConnection,
+    rows: &[(&MessageSource, &BinaryMessage, &MetHydro)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let position = column(rows, |(_, _, mh)| {
+        validate_position(mh.latitude, mh.longitude)
+    });
+    let source = SourceColumns::new(rows.iter().map(|(source, _, _)| *source));
+    sqlx::query!(
+        "INSERT INTO ais_met_hydro
+            (message_type, mmsi, latitude, longitude, position_accuracy, day, hour, minute,
+             wind_speed, wind_gust, wind_direction, wind_gust_direction,
+             air_temperature, relative_humidity, dew_point, air_pressure, air_pressure_tendency,
+             visibility, visibility_greater_than, water_level, water_level_trend,
+             surface_current_speed, surface_current_direction,
+             current2_speed, current2_direction, current2_depth,
+             current3_speed, current3_direction, current3_depth,
+             wave_height, wave_period, wave_direction, swell_height, swell_period, swell_direction,
+             sea_state, water_temperature, precipitation_type, salinity, ice,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, latitude, longitude, position_accuracy, day, hour, minute,
+               wind_speed, wind_gust, wind_direction, wind_gust_direction,
+               air_temperature, relative_humidity, dew_point, air_pressure, air_pressure_tendency,
+               visibility, visibility_greater_than, water_level, water_level_trend,
+               surface_current_speed, surface_current_direction,
+               current2_speed, current2_direction, current2_depth,
+               current3_speed, current3_direction, current3_depth,
+               wave_height, wave_period, wave_direction, swell_height, swell_period, swell_direction,
+               sea_state, water_temperature, precipitation_type, salinity, ice,
+               station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::float8[], $4::float8[], $5::bool[],
+                    $6::int4[], $7::int4[], $8::int4[],
+                    $9::int4[], $10::int4[], $11::int4[], $12::int4[],
+                    $13::float8[], $14::int4[], $15::float8[], $16::int4[], $17::int4[],
+                    $18::float8[], $19::bool[], $20::float8[], $21::int4[],
+                    $22::float8[], $23::int4[],
+                    $24::float8[], $25::int4[], $26::int4[],
+                    $27::float8[], $28::int4[], $29::int4[],
+                    $30::float8[], $31::int4[], $32::int4[], $33::float8[], $34::int4[], $35::int4[],
+                    $36::int4[], $37::float8[], $38::int4[], $39::float8[], $40::bool[],
+                    $41::text[], $42::timestamp[], $43::jsonb[], $44::text[], $45::timestamp[])
+            AS t(message_type, mmsi, latitude, longitude, position_accuracy, day, hour, minute,
+                 wind_speed, wind_gust, wind_direction, wind_gust_direction,
+                 air_temperature, relative_humidity, dew_point, air_pressure, air_pressure_tendency,
+                 visibility, visibility_greater_than, water_level, water_level_trend,
+                 surface_current_speed, surface_current_direction,
+                 current2_speed, current2_direction, current2_depth,
+                 current3_speed, current3_direction, current3_depth,
+                 wave_height, wave_period, wave_direction, swell_height, swell_period, swell_direction,
+                 sea_state, water_temperature, precipitation_type, salinity, ice,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, bm, _)| bm.message_type as i32),
+        &column(rows, |(_, bm, _)| bm.mmsi as i64),
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.position_accuracy),
+        &column(rows, |(_, _, mh)| mh.day.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.hour.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.minute.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.wind_speed.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.wind_gust.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.wind_direction.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.wind_gust_direction.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.air_temperature.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.relative_humidity.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.dew_point.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.air_pressure.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.air_pressure_tendency.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.visibility.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.visibility_greater_than),
+        &column(rows, |(_, _, mh)| mh.water_level.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.water_level_trend.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.currents[0].speed.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.currents[0].direction.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.currents[1].speed.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.currents[1].direction.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.currents[1].depth.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.currents[2].speed.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.currents[2].direction.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.currents[2].depth.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.wave_height.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.wave_period.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.wave_direction.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.swell_height.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.swell_period.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.swell_direction.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.sea_state.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.water_temperature.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.precipitation_type.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, mh)| mh.salinity.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, _, mh)| mh.ice) as &[Option<bool>],
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn insert_area_notices(
+    conn: &mut PgConnection,
+    rows: &[(&MessageSource, &BinaryMessage, &AreaNotice)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let source = SourceColumns::new(rows.iter().map(|(source, _, _)| *source));
+    sqlx::query!(
+        "INSERT INTO ais_area_notices
+            (message_type, mmsi, linkage_id, notice_type, month, day, hour, minute,
+             duration_minutes, subareas, station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, linkage_id, notice_type, month, day, hour, minute,
+               duration_minutes, subareas, station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::int4[], $4::int4[], $5::int4[], $6::int4[],
+                    $7::int4[], $8::int4[], $9::int4[], $10::jsonb[],
+                    $11::text[], $12::timestamp[], $13::jsonb[], $14::text[], $15::timestamp[])
+            AS t(message_type, mmsi, linkage_id, notice_type, month, day, hour, minute,
+                 duration_minutes, subareas, station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, bm, _)| bm.message_type as i32),
+        &column(rows, |(_, bm, _)| bm.mmsi as i64),
+        &column(rows, |(_, _, an)| an.linkage_id as i32),
+        &column(rows, |(_, _, an)| an.notice_type as i32),
+        &column(rows, |(_, _, an)| an.month.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, an)| an.day.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, an)| an.hour.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, an)| an.minute.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, _, an)| an.duration_minutes.map(|d| d as i32)) as &[Option<i32>],
+        &column(rows, |(_, _, an)| json!(an.subareas)),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn insert_aton_monitoring(
+    conn: &mut PgConnection,
+    rows: &[(&MessageSource, &BinaryMessage, &AtonMonitoring)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let source = SourceColumns::new(rows.iter().map(|(source, _, _)| *source));
+    sqlx::query!(
+        "INSERT INTO ais_aton_monitoring
+            (message_type, mmsi, dac, analogue_internal, analogue_external1, analogue_external2,
+             racon_status, light_status, health_status, digital_inputs, off_position,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, dac, analogue_internal, analogue_external1, analogue_external2,
+               racon_status, light_status, health_status, digital_inputs, off_position,
+               station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::int4[], $4::float8[], $5::float8[], $6::float8[],
+                    $7::text[], $8::text[], $9::text[], $10::int4[], $11::bool[],
+                    $12::text[], $13::timestamp[], $14::jsonb[], $15::text[], $16::timestamp[])
+            AS t(message_type, mmsi, dac, analogue_internal, analogue_external1, analogue_external2,
+                 racon_status, light_status, health_status, digital_inputs, off_position,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, bm, _)| bm.message_type as i32),
+        &column(rows, |(_, bm, _)| bm.mmsi as i64),
+        &column(rows, |(_, bm, _)| bm.dac as i32),
+        &column(rows, |(_, _, am)| am.analogue_internal as f64),
+        &column(rows, |(_, _, am)| am.analogue_external1 as f64),
+        &column(rows, |(_, _, am)| am.analogue_external2 as f64),
+        &column(rows, |(_, _, am)| enum_name(Some(am.status.racon))) as &[Option<String>],
+        &column(rows, |(_, _, am)| enum_name(Some(am.status.light))) as &[Option<String>],
+        &column(rows, |(_, _, am)| enum_name(Some(am.status.health))) as &[Option<String>],
+        &column(rows, |(_, _, am)| am.digital_inputs as i32),
+        &column(rows, |(_, _, am)| am.off_position),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
@@ -1,15 +1,691 @@
+use super::aton::upsert_aton_status;
+use super::binary::insert_binary_messages;
+use super::vessels::upsert_vessels;
+use crate::ais::binary::BinaryMessage;
+use crate::ais::msg21::AtonStatusPage;
+use crate::ais::validation::validate_position;
+use crate::client::envelope::{AisEnvelope, MessageSource};
+use ais::messages::AisMessage;
+use ais::messages::addressed_safety_related::AddressedSafetyRelatedMessage;
+use ais::messages::aid_to_navigation_report::AidToNavigationReport;
+use ais::messages::base_station_report::BaseStationReport;
+use ais::messages::extended_class_b_position_report::ExtendedClassBPositionReport;
+use ais::messages::long_range_ais_broadcast::LongRangeAisBroadcastMessage;
+use ais::messages::navigation::{Accuracy, Direction, RateOfTurn};
 use ais::messages::position_report::PositionReport;
-use sqlx::PgPool;
+use ais::messages::safety_related_broadcast::SafetyRelatedBroadcastMessage;
+use ais::messages::standard_class_b_position_report::StandardClassBPositionReport;
+use ais::messages::static_and_voyage_related_data::StaticAndVoyageRelatedData;
+use ais::messages::static_data_report::{MessagePart, StaticDataReport};
+use chrono::NaiveDateTime;
+use serde_json::Value as JsonValue;
+use sqlx::{PgConnection, PgPool};
+use std::fmt::Debug;
 
-pub async fn insert_position_report(pool: &PgPool, pos: PositionReport) -> Result<(), sqlx::Error> {
+// Enums from the ais crate have no numeric representation, so they are stored by name
+pub fn enum_name<T: Debug>(value: Option<T>) -> Option<String> {
+    value.map(|v| format!("{:?}", v))
+}
+
+// Signed rate of turn in degrees per minute (negative to port); None when not available
+fn rate_of_turn(rot: Option<RateOfTurn>) -> Option<f64> {
+    let rot = rot?;
+    let rate = rot.rate()? as f64;
+    match rot.direction() {
+        Some(Direction::Port) => Some(-rate),
+        _ => Some(rate),
+    }
+}
+
+// One bind array per column. Rows are inserted with UNNEST over these, which
+// keeps the statement fixed (and checked by query!) whatever the batch size.
+pub fn column<R, T>(rows: &[R], value: impl Fn(&R) -> T) -> Vec<T> {
+    rows.iter().map(value).collect()
+}
+
+// The reception columns every message table ends with. heard_by goes over as
+// JSON, since UNNEST would flatten an array of arrays.
+pub struct SourceColumns {
+    pub station: Vec<String>,
+    pub received_at: Vec<NaiveDateTime>,
+    pub heard_by: Vec<JsonValue>,
+    pub tag_source: Vec<Option<String>>,
+    pub tag_time: Vec<Option<NaiveDateTime>>,
+}
+
+impl SourceColumns {
+    pub fn new<'a>(sources: impl Iterator<Item = &'a MessageSource>) -> Self {
+        let mut columns = Self {
+            station: Vec::new(),
+            received_at: Vec::new(),
+            heard_by: Vec::new(),
+            tag_source: Vec::new(),
+            tag_time: Vec::new(),
+        };
+        for source in sources {
+            columns.station.push(source.station.clone());
+            columns.received_at.push(source.received_at.naive_utc());
+            columns
+                .heard_by
+                .push(JsonValue::from(source.heard_by.clone()));
+            columns.tag_source.push(source.tag_source.clone());
+            columns
+                .tag_time
+                .push(source.tag_time.map(|t| t.naive_utc()));
+        }
+        columns
+    }
+}
+
+// An AtoN report with its status page, read from the payload (None if it couldn't be)
+pub type AtonRow = (MessageSource, AidToNavigationReport, Option<AtonStatusPage>);
+
+// Decoded messages waiting to be written, grouped by destination table
+#[derive(Default)]
+pub struct Batch {
+    positions: Vec<(MessageSource, PositionReport)>,
+    base_stations: Vec<(MessageSource, BaseStationReport)>,
+    static_voyage: Vec<(MessageSource, StaticAndVoyageRelatedData)>,
+    class_b: Vec<(MessageSource, StandardClassBPositionReport)>,
+    extended_class_b: Vec<(MessageSource, ExtendedClassBPositionReport)>,
+    addressed_safety: Vec<(MessageSource, AddressedSafetyRelatedMessage)>,
+    broadcast_safety: Vec<(MessageSource, SafetyRelatedBroadcastMessage)>,
+    atons: Vec<AtonRow>,
+    static_data: Vec<(MessageSource, StaticDataReport)>,
+    long_range: Vec<(MessageSource, LongRangeAisBroadcastMessage)>,
+    binary: Vec<(MessageSource, BinaryMessage)>,
+    len: usize,
+}
+
+impl Batch {
+    // Queues a message for its table; message types we don't store are ignored
+    pub fn push(&mut self, envelope: AisEnvelope) {
+        let AisEnvelope {
+            source,
+            message,
+            payload,
+            fill_bits,
+            ..
+        } = envelope;
+        match message {
+            AisMessage::PositionReport(pos) => self.positions.push((source, pos)),
+            AisMessage::BaseStationReport(bs) => self.base_stations.push((source, bs)),
+            AisMessage::StaticAndVoyageRelatedData(sv) => self.static_voyage.push((source, sv)),
+            AisMessage::StandardClassBPositionReport(cb) => self.class_b.push((source, cb)),
+            AisMessage::ExtendedClassBPositionReport(ecb) => {
+                self.extended_class_b.push((source, ecb))
+            }
+            AisMessage::AddressedSafetyRelatedMessage(sm) => {
+                self.addressed_safety.push((source, sm))
+            }
+            AisMessage::SafetyRelatedBroadcastMessage(sm) => {
+                self.broadcast_safety.push((source, sm))
+            }
+            AisMessage::AidToNavigationReport(aton) => {
+                let page = AtonStatusPage::from_payload(&payload, fill_bits).ok();
+                self.atons.push((source, aton, page))
+            }
+            AisMessage::StaticDataReport(sdr) => self.static_data.push((source, sdr)),
+            AisMessage::LongRangeAisBroadcastMessage(lr) => self.long_range.push((source, lr)),
+            AisMessage::BinaryAddressedMessage(_) | AisMessage::BinaryBroadcastMessage(_) => {
+                match BinaryMessage::new(message, &payload, fill_bits) {
+                    Some(bm) => self.binary.push((source, bm)),
+                    None => return,
+                }
+            }
+            _ => return,
+        }
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// Writes a whole batch in one transaction, one INSERT per table
+pub async fn insert_batch(pool: &PgPool, batch: &Batch) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    insert_position_reports(&mut tx, &batch.positions).await?;
+    insert_base_station_reports(&mut tx, &batch.base_stations).await?;
+    insert_static_voyage_data(&mut tx, &batch.static_voyage).await?;
+    insert_class_b_position_reports(&mut tx, &batch.class_b).await?;
+    insert_extended_class_b_position_reports(&mut tx, &batch.extended_class_b).await?;
+    insert_addressed_safety_messages(&mut tx, &batch.addressed_safety).await?;
+    insert_broadcast_safety_messages(&mut tx, &batch.broadcast_safety).await?;
+    insert_aton_reports(&mut tx, &batch.atons).await?;
+    insert_static_data_reports(&mut tx, &batch.static_data).await?;
+    insert_long_range_reports(&mut tx, &batch.long_range).await?;
+    insert_binary_messages(&mut tx, &batch.binary).await?;
+    upsert_vessels(&mut tx, &batch.static_voyage, &batch.static_data).await?;
+    upsert_aton_status(&mut tx, &batch.atons).await?;
+    tx.commit().await
+}
+
+pub async fn insert_position_reports(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, PositionReport)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let position = column(rows, |(_, pos)| {
+        validate_position(pos.latitude, pos.longitude)
+    });
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_position_reports
+            (message_type, mmsi, latitude, longitude, navigation_status, rate_of_turn,
+             speed_over_ground, course_over_ground, true_heading, position_accuracy,
+             timestamp, maneuver_indicator, raim, station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, latitude, longitude, navigation_status, rate_of_turn,
+               speed_over_ground, course_over_ground, true_heading, position_accuracy,
+               timestamp, maneuver_indicator, raim, station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::float8[], $4::float8[], $5::text[], $6::float8[],
+                    $7::float8[], $8::float8[], $9::int4[], $10::bool[],
+                    $11::int4[], $12::text[], $13::bool[],
+                    $14::text[], $15::timestamp[], $16::jsonb[], $17::text[], $18::timestamp[])
+            AS t(message_type, mmsi, latitude, longitude, navigation_status, rate_of_turn,
+                 speed_over_ground, course_over_ground, true_heading, position_accuracy,
+                 timestamp, maneuver_indicator, raim, station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, pos)| pos.message_type as i32),
+        &column(rows, |(_, pos)| pos.mmsi as i64),
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(rows, |(_, pos)| enum_name(pos.navigation_status)) as &[Option<String>],
+        &column(rows, |(_, pos)| rate_of_turn(pos.rate_of_turn)) as &[Option<f64>],
+        &column(rows, |(_, pos)| pos.speed_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, pos)| pos.course_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, pos)| pos.true_heading.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, pos)| pos.position_accuracy == Accuracy::Dgps),
+        &column(rows, |(_, pos)| pos.timestamp as i32),
+        &column(rows, |(_, pos)| enum_name(pos.maneuver_indicator)) as &[Option<String>],
+        &column(rows, |(_, pos)| pos.raim),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_base_station_reports(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, BaseStationReport)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let position = column(rows, |(_, bs)| validate_position(bs.latitude, bs.longitude));
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_base_station_reports
+            (message_type, mmsi, year, month, day, hour, minute, second, latitude, longitude,
+             epfd_type, raim, station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, year, month, day, hour, minute, second, latitude, longitude,
+               epfd_type, raim, station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::int4[], $4::int4[], $5::int4[], $6::int4[],
+                    $7::int4[], $8::int4[], $9::float8[], $10::float8[], $11::text[], $12::bool[],
+                    $13::text[], $14::timestamp[], $15::jsonb[], $16::text[], $17::timestamp[])
+            AS t(message_type, mmsi, year, month, day, hour, minute, second, latitude, longitude,
+                 epfd_type, raim, station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, bs)| bs.message_type as i32),
+        &column(rows, |(_, bs)| bs.mmsi as i64),
+        &column(rows, |(_, bs)| bs.year.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, bs)| bs.month.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, bs)| bs.day.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, bs)| bs.hour as i32),
+        &column(rows, |(_, bs)| bs.minute.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, bs)| bs.second.map(i32::from)) as &[Option<i32>],
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(rows, |(_, bs)| enum_name(bs.epfd_type)) as &[Option<String>],
+        &column(rows, |(_, bs)| bs.raim),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_static_voyage_data(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, StaticAndVoyageRelatedData)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_static_voyage_data
+            (message_type, mmsi, ais_version, imo_number, callsign, vessel_name, ship_type,
+             dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+             epfd_type, eta_month, eta_day, eta_hour, eta_minute, draught, destination,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, ais_version, imo_number, callsign, vessel_name, ship_type,
+               dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+               epfd_type, eta_month, eta_day, eta_hour, eta_minute, draught, destination,
+               station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::int4[], $4::int8[], $5::text[], $6::text[],
+                    $7::int4[], $8::int4[], $9::int4[], $10::int4[], $11::int4[],
+                    $12::text[], $13::int4[], $14::int4[], $15::int4[], $16::int4[],
+                    $17::float8[], $18::text[],
+                    $19::text[], $20::timestamp[], $21::jsonb[], $22::text[], $23::timestamp[])
+            AS t(message_type, mmsi, ais_version, imo_number, callsign, vessel_name, ship_type,
+                 dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+                 epfd_type, eta_month, eta_day, eta_hour, eta_minute, draught, destination,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, sv)| sv.message_type as i32),
+        &column(rows, |(_, sv)| sv.mmsi as i64),
+        &column(rows, |(_, sv)| sv.ais_version as i32),
+        &column(rows, |(_, sv)| sv.imo_number as i64),
+        &column(rows, |(_, sv)| sv.callsign.to_string()),
+        &column(rows, |(_, sv)| sv.vessel_name.to_string()),
+        &column(rows, |(_, sv)| sv.ship_type.map(|t| u8::from(t) as i32)) as &[Option<i32>],
+        &column(rows, |(_, sv)| sv.dimension_to_bow as i32),
+        &column(rows, |(_, sv)| sv.dimension_to_stern as i32),
+        &column(rows, |(_, sv)| sv.dimension_to_port as i32),
+        &column(rows, |(_, sv)| sv.dimension_to_starboard as i32),
+        &column(rows, |(_, sv)| enum_name(sv.epfd_type)) as &[Option<String>],
+        &column(rows, |(_, sv)| sv.eta_month_utc.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, sv)| sv.eta_day_utc.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, sv)| sv.eta_hour_utc as i32),
+        &column(rows, |(_, sv)| sv.eta_minute_utc.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, sv)| sv.draught as f64),
+        &column(rows, |(_, sv)| sv.destination.to_string()),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_class_b_position_reports(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, StandardClassBPositionReport)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let position = column(rows, |(_, cb)| validate_position(cb.latitude, cb.longitude));
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_class_b_position_reports
+            (message_type, mmsi, latitude, longitude, speed_over_ground, course_over_ground,
+             true_heading, timestamp, raim, station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, latitude, longitude, speed_over_ground, course_over_ground,
+               true_heading, timestamp, raim, station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::float8[], $4::float8[], $5::float8[], $6::float8[],
+                    $7::int4[], $8::int4[], $9::bool[],
+                    $10::text[], $11::timestamp[], $12::jsonb[], $13::text[], $14::timestamp[])
+            AS t(message_type, mmsi, latitude, longitude, speed_over_ground, course_over_ground,
+                 true_heading, timestamp, raim, station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, cb)| cb.message_type as i32),
+        &column(rows, |(_, cb)| cb.mmsi as i64),
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(rows, |(_, cb)| cb.speed_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, cb)| cb.course_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, cb)| cb.true_heading.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, cb)| cb.timestamp as i32),
+        &column(rows, |(_, cb)| cb.raim),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_extended_class_b_position_reports(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, ExtendedClassBPositionReport)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let position = column(rows, |(_, ecb)| {
+        validate_position(ecb.latitude, ecb.longitude)
+    });
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_class_b_position_reports
+            (message_type, mmsi, latitude, longitude, speed_over_ground, course_over_ground,
+             true_heading, timestamp, raim, vessel_name, ship_type,
+             dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, latitude, longitude, speed_over_ground, course_over_ground,
+               true_heading, timestamp, raim, vessel_name, ship_type,
+               dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+               station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::float8[], $4::float8[], $5::float8[], $6::float8[],
+                    $7::int4[], $8::int4[], $9::bool[], $10::text[], $11::int4[],
+                    $12::int4[], $13::int4[], $14::int4[], $15::int4[],
+                    $16::text[], $17::timestamp[], $18::jsonb[], $19::text[], $20::timestamp[])
+            AS t(message_type, mmsi, latitude, longitude, speed_over_ground, course_over_ground,
+                 true_heading, timestamp, raim, vessel_name, ship_type,
+                 dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, ecb)| ecb.message_type as i32),
+        &column(rows, |(_, ecb)| ecb.mmsi as i64),
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(rows, |(_, ecb)| ecb.speed_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, ecb)| ecb.course_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, ecb)| ecb.true_heading.map(i32::from)) as &[Option<i32>],
+        &column(rows, |(_, ecb)| ecb.timestamp as i32),
+        &column(rows, |(_, ecb)| ecb.raim),
+        &column(rows, |(_, ecb)| ecb.name.to_string()),
+        &column(rows, |(_, ecb)| {
+            ecb.type_of_ship_and_cargo.map(|t| u8::from(t) as i32)
+        }) as &[Option<i32>],
+        &column(rows, |(_, ecb)| ecb.dimension_to_bow as i32),
+        &column(rows, |(_, ecb)| ecb.dimension_to_stern as i32),
+        &column(rows, |(_, ecb)| ecb.dimension_to_port as i32),
+        &column(rows, |(_, ecb)| ecb.dimension_to_starboard as i32),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_addressed_safety_messages(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, AddressedSafetyRelatedMessage)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_safety_messages
+            (message_type, mmsi, dest_mmsi, seqno, retransmit, text,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, dest_mmsi, seqno, retransmit, text, station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::int8[], $4::int4[], $5::bool[], $6::text[],
+                    $7::text[], $8::timestamp[], $9::jsonb[], $10::text[], $11::timestamp[])
+            AS t(message_type, mmsi, dest_mmsi, seqno, retransmit, text,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, sm)| sm.message_type as i32),
+        &column(rows, |(_, sm)| sm.mmsi as i64),
+        &column(rows, |(_, sm)| sm.dest_mmsi as i64),
+        &column(rows, |(_, sm)| sm.seqno as i32),
+        &column(rows, |(_, sm)| sm.retransmit),
+        &column(rows, |(_, sm)| sm.text.to_string()),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_broadcast_safety_messages(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, SafetyRelatedBroadcastMessage)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_safety_messages
+            (message_type, mmsi, text, station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, text, station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::text[],
+                    $4::text[], $5::timestamp[], $6::jsonb[], $7::text[], $8::timestamp[])
+            AS t(message_type, mmsi, text, station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, sm)| sm.message_type as i32),
+        &column(rows, |(_, sm)| sm.mmsi as i64),
+        &column(rows, |(_, sm)| sm.text.to_string()),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_aton_reports(
+    conn: &mut PgConnection,
+    rows: &[AtonRow],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let position = column(rows, |(_, aton, _)| {
+        validate_position(aton.latitude, aton.longitude)
+    });
+    let status = column(rows, |(_, _, page)| page.and_then(|p| p.monitored()));
+    let source = SourceColumns::new(rows.iter().map(|(source, _, _)| source));
+    sqlx::query!(
+        "INSERT INTO ais_aton_reports
+            (message_type, mmsi, aid_type, name, latitude, longitude,
+             dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+             epfd_type, utc_second, off_position, regional_reserved, raim, virtual_aid,
+             assigned_mode, status_page, racon_status, light_status, health_status,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, aid_type, name, latitude, longitude,
+               dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+               epfd_type, utc_second, off_position, regional_reserved, raim, virtual_aid,
+               assigned_mode, status_page, racon_status, light_status, health_status,
+               station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::text[], $4::text[], $5::float8[], $6::float8[],
+                    $7::int4[], $8::int4[], $9::int4[], $10::int4[],
+                    $11::text[], $12::int4[], $13::bool[], $14::int4[], $15::bool[], $16::bool[],
+                    $17::bool[], $18::int4[], $19::text[], $20::text[], $21::text[],
+                    $22::text[], $23::timestamp[], $24::jsonb[], $25::text[], $26::timestamp[])
+            AS t(message_type, mmsi, aid_type, name, latitude, longitude,
+                 dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+                 epfd_type, utc_second, off_position, regional_reserved, raim, virtual_aid,
+                 assigned_mode, status_page, racon_status, light_status, health_status,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, aton, _)| aton.message_type as i32),
+        &column(rows, |(_, aton, _)| aton.mmsi as i64),
+        &column(rows, |(_, aton, _)| enum_name(aton.aid_type.as_ref())) as &[Option<String>],
+        &column(rows, |(_, aton, _)| aton.name.to_string()),
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(rows, |(_, aton, _)| aton.dimension_to_bow as i32),
+        &column(rows, |(_, aton, _)| aton.dimension_to_stern as i32),
+        &column(rows, |(_, aton, _)| aton.dimension_to_port as i32),
+        &column(rows, |(_, aton, _)| aton.dimension_to_starboard as i32),
+        &column(rows, |(_, aton, _)| enum_name(aton.epfd_type)) as &[Option<String>],
+        &column(rows, |(_, aton, _)| aton.utc_second as i32),
+        &column(rows, |(_, aton, _)| aton.off_position),
+        &column(rows, |(_, aton, _)| aton.regional_reserved as i32),
+        &column(rows, |(_, aton, _)| aton.raim),
+        &column(rows, |(_, aton, _)| aton.virtual_aid),
+        &column(rows, |(_, aton, _)| aton.assigned_mode),
+        &column(rows, |(_, _, page)| page.map(|p| p.page_id() as i32)) as &[Option<i32>],
+        &column(&status, |s| enum_name(s.map(|s| s.racon))) as &[Option<String>],
+        &column(&status, |s| enum_name(s.map(|s| s.light))) as &[Option<String>],
+        &column(&status, |s| enum_name(s.map(|s| s.health))) as &[Option<String>],
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_static_data_reports(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, StaticDataReport)],
+) -> Result<(), sqlx::Error> {
+    let mut part = Vec::new();
+    let mut vessel_name = Vec::new();
+    let mut ship_type = Vec::new();
+    let mut vendor_id = Vec::new();
+    let mut callsign = Vec::new();
+    let mut dimensions: [Vec<Option<i32>>; 4] = Default::default();
+    let mut reports = Vec::new();
+    for row in rows {
+        let (_, sdr) = row;
+        match &sdr.message_part {
+            MessagePart::PartA { vessel_name: name } => {
+                part.push("A".to_string());
+                vessel_name.push(Some(name.to_string()));
+                ship_type.push(None);
+                vendor_id.push(None);
+                callsign.push(None);
+                for dimension in &mut dimensions {
+                    dimension.push(None);
+                }
+            }
+            MessagePart::PartB {
+                ship_type: kind,
+                vendor_id: vendor,
+                callsign: call,
+                dimension_to_bow,
+                dimension_to_stern,
+                dimension_to_port,
+                dimension_to_starboard,
+                ..
+            } => {
+                part.push("B".to_string());
+                vessel_name.push(None);
+                ship_type.push(kind.map(|t| u8::from(t) as i32));
+                vendor_id.push(Some(vendor.to_string()));
+                callsign.push(Some(call.to_string()));
+                let values = [
+                    dimension_to_bow,
+                    dimension_to_stern,
+                    dimension_to_port,
+                    dimension_to_starboard,
+                ];
+                for (dimension, value) in dimensions.iter_mut().zip(values) {
+                    dimension.push(Some(*value as i32));
+                }
+            }
+            // Part numbers 2 and 3 are reserved
+            MessagePart::Unknown(_) => continue,
+        }
+        reports.push(row);
+    }
+    if reports.is_empty() {
+        return Ok(());
+    }
+
+    let source = SourceColumns::new(reports.iter().map(|(source, _)| source));
+    let [to_bow, to_stern, to_port, to_starboard] = dimensions;
+    sqlx::query!(
+        "INSERT INTO ais_static_data_reports
+            (message_type, mmsi, part, vessel_name, ship_type, vendor_id, callsign,
+             dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, part, vessel_name, ship_type, vendor_id, callsign,
+               dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+               station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::text[], $4::text[], $5::int4[], $6::text[],
+                    $7::text[], $8::int4[], $9::int4[], $10::int4[], $11::int4[],
+                    $12::text[], $13::timestamp[], $14::jsonb[], $15::text[], $16::timestamp[])
+            AS t(message_type, mmsi, part, vessel_name, ship_type, vendor_id, callsign,
+                 dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(&reports, |(_, sdr)| sdr.message_type as i32),
+        &column(&reports, |(_, sdr)| sdr.mmsi as i64),
+        &part,
+        &vessel_name as &[Option<String>],
+        &ship_type as &[Option<i32>],
+        &vendor_id as &[Option<String>],
+        &callsign as &[Option<String>],
+        &to_bow as &[Option<i32>],
+        &to_stern as &[Option<i32>],
+        &to_port as &[Option<i32>],
+        &to_starboard as &[Option<i32>],
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_long_range_reports(
+    conn: &mut PgConnection,
+    rows: &[(MessageSource, LongRangeAisBroadcastMessage)],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let position = column(rows, |(_, lr)| validate_position(lr.latitude, lr.longitude));
+    let source = SourceColumns::new(rows.iter().map(|(source, _)| source));
     sqlx::query!(
-        "INSERT INTO ais_position_reports (message_type, mmsi, latitude, longitude) VALUES ($1, $2, $3, $4)",
-        pos.message_type as i32,
-        pos.mmsi as i64,
-        pos.latitude.unwrap_or(0.0) as f64,
-        pos.longitude.unwrap_or(0.0) as f64
+        "INSERT INTO ais_long_range_reports
+            (message_type, mmsi, navigation_status, latitude, longitude,
+             speed_over_ground, course_over_ground, raim, gnss_position_status,
+             station, received_at, heard_by, tag_source, tag_time)
+        SELECT message_type, mmsi, navigation_status, latitude, longitude,
+               speed_over_ground, course_over_ground, raim, gnss_position_status,
+               station, received_at,
+               ARRAY(SELECT jsonb_array_elements_text(heard_by)), tag_source, tag_time
+        FROM UNNEST($1::int4[], $2::int8[], $3::text[], $4::float8[], $5::float8[],
+                    $6::float8[], $7::float8[], $8::bool[], $9::bool[],
+                    $10::text[], $11::timestamp[], $12::jsonb[], $13::text[], $14::timestamp[])
+            AS t(message_type, mmsi, navigation_status, latitude, longitude,
+                 speed_over_ground, course_over_ground, raim, gnss_position_status,
+                 station, received_at, heard_by, tag_source, tag_time)",
+        &column(rows, |(_, lr)| lr.message_type as i32),
+        &column(rows, |(_, lr)| lr.mmsi as i64),
+        &column(rows, |(_, lr)| enum_name(lr.navigation_status)) as &[Option<String>],
+        &column(&position, |p| p.map(|(lat, _)| lat)) as &[Option<f64>],
+        &column(&position, |p| p.map(|(_, lon)| lon)) as &[Option<f64>],
+        &column(rows, |(_, lr)| lr.speed_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, lr)| lr.course_over_ground.map(f64::from)) as &[Option<f64>],
+        &column(rows, |(_, lr)| lr.raim),
+        &column(rows, |(_, lr)| lr.gnss_position_status),
+        &source.station,
+        &source.received_at,
+        &source.heard_by,
+        &source.tag_source as &[Option<String>],
+        &source.tag_time as &[Option<NaiveDateTime>],
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
     Ok(())
 }
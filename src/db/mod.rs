@@ -1 +1,8 @@
+pub mod alarms;
+pub mod aton;
+pub mod binary;
 pub mod database;
+pub mod spool;
+pub mod station_stats;
+pub mod vessels;
+pub mod writer;
@@ -0,0 +1,349 @@
+use crate::ais::fragments::Framing;
+use crate::client::envelope::{AisEnvelope, MessageSource};
+use crate::config::{DropPolicy, SpoolConfig};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, Lines};
+
+// The spool is split into this many files, so DropOldest can delete the oldest
+// one instead of rewriting everything
+const SEGMENTS: u64 = 16;
+
+// What we keep of a message while the database is away. The decoded AisMessage is
+// not serialisable, so the armored payload is stored and decoded again on replay.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpooledMessage {
+    pub source: MessageSource,
+    pub payload: String,
+    pub fill_bits: u8,
+    pub framing: Framing,
+}
+
+impl SpooledMessage {
+    pub fn from_envelope(envelope: &AisEnvelope) -> Self {
+        Self {
+            source: envelope.source.clone(),
+            payload: envelope.payload.clone(),
+            fill_bits: envelope.fill_bits,
+            framing: envelope.framing.clone(),
+        }
+    }
+
+    pub fn into_envelope(self) -> anyhow::Result<AisEnvelope> {
+        let unarmored = ais::messages::unarmor(self.payload.as_bytes(), self.fill_bits as usize)
+            .map_err(|e| anyhow::anyhow!("Invalid spooled payload: {}", e))?;
+        let message = ais::messages::parse(&unarmored)
+            .map_err(|e| anyhow::anyhow!("Failed to decode spooled payload: {}", e))?;
+        Ok(AisEnvelope {
+            source: self.source,
+            payload: self.payload,
+            fill_bits: self.fill_bits,
+            framing: self.framing,
+            message,
+        })
+    }
+}
+
+// A message the database refused, kept with the reason for a later look
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    #[serde(flatten)]
+    message: &'a SpooledMessage,
+    error: &'a str,
+    failed_at: DateTime<Utc>,
+}
+
+// Appends a message the database rejected to the dead-letter file
+pub async fn dead_letter(path: &Path, message: &SpooledMessage, error: &str) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(&DeadLetter {
+        message,
+        error,
+        failed_at: Utc::now(),
+    })?;
+    line.push('\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+// One file of the spool, named after the spool path with a sequence number
+struct Segment {
+    path: PathBuf,
+    seq: u64,
+    bytes: u64,
+    messages: usize,
+}
+
+// JSON-lines files holding messages that could not be written to Postgres,
+// oldest segment first
+pub struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    drop_policy: DropPolicy,
+    segment_bytes: u64, // A new segment is started once the newest reaches this
+    segments: VecDeque<Segment>,
+    size: u64,
+    cursor: Option<Cursor>, // Where replay is in the oldest segment
+}
+
+// An open reader on the oldest segment and how many of its lines were handed out
+struct Cursor {
+    lines: Lines<BufReader<File>>,
+    read: usize,
+}
+
+impl Spool {
+    // Opens the spool, picking up whatever a previous run left behind
+    pub async fn open(config: &SpoolConfig) -> anyhow::Result<Self> {
+        let mut spool = Self {
+            path: config.path.clone(),
+            max_bytes: config.max_bytes,
+            drop_policy: config.drop_policy,
+            segment_bytes: (config.max_bytes / SEGMENTS).max(1),
+            segments: VecDeque::new(),
+            size: 0,
+            cursor: None,
+        };
+
+        let mut seqs = Vec::new();
+        let dir = match spool.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let prefix = spool.segment_prefix();
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let seq = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(prefix.as_str()))
+                .and_then(|seq| seq.parse::<u64>().ok());
+            if let Some(seq) = seq {
+                seqs.push(seq);
+            }
+        }
+        seqs.sort_unstable();
+
+        for seq in seqs {
+            let path = spool.segment_path(seq);
+            let bytes = fs::metadata(&path).await?.len();
+            let mut lines = BufReader::new(File::open(&path).await?).lines();
+            let mut messages = 0;
+            while let Some(line) = lines.next_line().await? {
+                if !line.is_empty() {
+                    messages += 1;
+                }
+            }
+            spool.size += bytes;
+            spool.segments.push_back(Segment {
+                path,
+                seq,
+                bytes,
+                messages,
+            });
+        }
+
+        if spool.size > 0 {
+            println!(
+                "Spool {} holds {} bytes in {} files from a previous run",
+                spool.path.display(),
+                spool.size,
+                spool.segments.len()
+            );
+        }
+        Ok(spool)
+    }
+
+    // Segment files are "<file name>.<seq>" next to the configured path
+    fn segment_prefix(&self) -> String {
+        let name = self.path.file_name().unwrap_or_default();
+        format!("{}.", name.to_string_lossy())
+    }
+
+    fn segment_path(&self, seq: u64) -> PathBuf {
+        let mut name = OsString::from(self.segment_prefix());
+        name.push(format!("{:06}", seq));
+        self.path.with_file_name(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    // Appends messages to the spool and returns how many had to be dropped to stay within bounds
+    pub async fn append(&mut self, messages: &[SpooledMessage]) -> anyhow::Result<usize> {
+        let mut lines = Vec::with_capacity(messages.len());
+        for message in messages {
+            let mut line = serde_json::to_string(message)?;
+            line.push('\n');
+            lines.push(line);
+        }
+
+        let mut dropped = 0;
+        if self.drop_policy == DropPolicy::DropNewest {
+            // Keep what is already spooled, take new lines only while they fit
+            let mut room = self.max_bytes.saturating_sub(self.size);
+            let fitting = lines
+                .iter()
+                .take_while(|line| {
+                    let fits = line.len() as u64 <= room;
+                    if fits {
+                        room -= line.len() as u64;
+                    }
+                    fits
+                })
+                .count();
+            dropped = lines.len() - fitting;
+            lines.truncate(fitting);
+        }
+
+        let mut lines = lines.into_iter().peekable();
+        while lines.peek().is_some() {
+            let full = self
+                .segments
+                .back()
+                .is_none_or(|segment| segment.bytes >= self.segment_bytes);
+            if full {
+                let seq = self.segments.back().map_or(0, |segment| segment.seq + 1);
+                self.segments.push_back(Segment {
+                    path: self.segment_path(seq),
+                    seq,
+                    bytes: 0,
+                    messages: 0,
+                });
+            }
+            let segment = self.segments.back_mut().expect("a segment was just added");
+            let mut data = String::new();
+            let mut messages = 0;
+            while segment.bytes + (data.len() as u64) < self.segment_bytes
+                && let Some(line) = lines.next()
+            {
+                data.push_str(&line);
+                messages += 1;
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&segment.path)
+                .await?;
+            file.write_all(data.as_bytes()).await?;
+            file.flush().await?;
+            // Whatever is spooled has to survive a power cut, that's what it's for
+            file.sync_data().await?;
+            segment.bytes += data.len() as u64;
+            segment.messages += messages;
+            self.size += data.len() as u64;
+        }
+
+        // DropOldest: discard whole segments from the front until the spool fits
+        // again. The newest one stays, it holds what was just written.
+        while self.size > self.max_bytes && self.segments.len() > 1 {
+            dropped += self.remove_oldest().await?;
+        }
+
+        Ok(dropped)
+    }
+
+    // The oldest segment's lines, read as they are needed
+    async fn read_oldest(&self) -> anyhow::Result<Option<Lines<BufReader<File>>>> {
+        let Some(segment) = self.segments.front() else {
+            return Ok(None);
+        };
+        let file = File::open(&segment.path).await?;
+        Ok(Some(BufReader::new(file).lines()))
+    }
+
+    // The next `max` lines for replay, oldest first, all from one segment. A segment
+    // is deleted once a call finds it exhausted, as everything handed out before has
+    // been written by then. None once the spool is empty.
+    pub async fn next_lines(&mut self, max: usize) -> anyhow::Result<Option<Vec<String>>> {
+        loop {
+            let cursor = match &mut self.cursor {
+                Some(cursor) => cursor,
+                None => match self.read_oldest().await? {
+                    Some(lines) => self.cursor.insert(Cursor { lines, read: 0 }),
+                    None => return Ok(None),
+                },
+            };
+            let mut lines = Vec::new();
+            while lines.len() < max
+                && let Some(line) = cursor.lines.next_line().await?
+            {
+                cursor.read += 1;
+                lines.push(line);
+            }
+            if !lines.is_empty() {
+                return Ok(Some(lines));
+            }
+            self.remove_oldest().await?;
+        }
+    }
+
+    // Replay stopped with the last `unwritten` lines handed out not written. Those
+    // before them are dropped from the segment, the next call starts at the rest.
+    pub async fn rewind(&mut self, unwritten: usize) -> anyhow::Result<()> {
+        let Some(cursor) = self.cursor.take() else {
+            return Ok(());
+        };
+        self.skip_oldest(cursor.read.saturating_sub(unwritten))
+            .await
+    }
+
+    // Deletes the oldest segment once it has been replayed or has to make room,
+    // returning how many messages it held
+    async fn remove_oldest(&mut self) -> anyhow::Result<usize> {
+        let Some(segment) = self.segments.pop_front() else {
+            return Ok(0);
+        };
+        // Lines replay already wrote are not lost with it
+        let replayed = self.cursor.take().map_or(0, |cursor| cursor.read);
+        match fs::remove_file(&segment.path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.size -= segment.bytes;
+        Ok(segment.messages.saturating_sub(replayed))
+    }
+
+    // Drops the first `lines` lines of the oldest segment, after they were replayed.
+    // Only that one segment is copied.
+    async fn skip_oldest(&mut self, lines: usize) -> anyhow::Result<()> {
+        if lines == 0 {
+            return Ok(());
+        }
+        let Some(mut reader) = self.read_oldest().await? else {
+            return Ok(());
+        };
+        let segment = self.segments.front_mut().expect("read_oldest found one");
+        let tmp = segment.path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp).await?);
+        let (mut index, mut bytes, mut messages) = (0, 0, 0);
+        while let Some(line) = reader.next_line().await? {
+            index += 1;
+            if index <= lines || line.is_empty() {
+                continue;
+            }
+            out.write_all(line.as_bytes()).await?;
+            out.write_all(b"\n").await?;
+            bytes += line.len() as u64 + 1;
+            messages += 1;
+        }
+        out.flush().await?;
+        fs::rename(&tmp, &segment.path).await?;
+        self.size = self.size - segment.bytes + bytes;
+        segment.bytes = bytes;
+        segment.messages = messages;
+        Ok(())
+    }
+}
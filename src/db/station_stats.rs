@@ -0,0 +1,89 @@
+use super::database::{column, enum_name};
+use crate::client::stats::{StationRegistry, StationSample};
+use chrono::{NaiveDateTime, Utc};
+use serde_json::json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+// How often samples past the retention period are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Writes every station's counters to `station_stats` once per interval, and
+// deletes samples older than `retention`
+pub async fn run(
+    pool: Arc<PgPool>,
+    stations: Arc<StationRegistry>,
+    interval: Duration,
+    retention: Duration,
+) {
+    let mut tick = time::interval(interval);
+    tick.tick().await; // The first tick is immediate and would record an empty interval
+    let mut prune = time::interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {
+                let samples = stations.sample();
+                if let Err(e) = insert_samples(&pool, &samples).await {
+                    eprintln!("Failed to write station stats: {}", e);
+                }
+            }
+            _ = prune.tick() => {
+                if let Err(e) = delete_older_than(&pool, retention).await {
+                    eprintln!("Failed to delete old station stats: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn delete_older_than(pool: &PgPool, retention: Duration) -> Result<(), sqlx::Error> {
+    let Ok(retention) = chrono::Duration::from_std(retention) else {
+        return Ok(()); // Longer than chrono can represent, nothing is that old
+    };
+    let cutoff = (Utc::now() - retention).naive_utc();
+    let deleted = sqlx::query!("DELETE FROM station_stats WHERE sampled_at < $1", cutoff)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    if deleted > 0 {
+        println!(
+            "Deleted {} station stats samples from before {}",
+            deleted,
+            cutoff.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    Ok(())
+}
+
+async fn insert_samples(pool: &PgPool, samples: &[StationSample]) -> Result<(), sqlx::Error> {
+    if samples.is_empty() {
+        return Ok(());
+    }
+    let sampled_at = Utc::now().naive_utc();
+    sqlx::query!(
+        "INSERT INTO station_stats
+            (station, sampled_at, state, last_message_at, messages, messages_by_type,
+             parse_errors, checksum_errors, reconnects)
+        SELECT station, $2, state, last_message_at, messages, messages_by_type,
+               parse_errors, checksum_errors, reconnects
+        FROM UNNEST($1::text[], $3::text[], $4::timestamp[], $5::int8[], $6::jsonb[],
+                    $7::int8[], $8::int8[], $9::int8[])
+            AS t(station, state, last_message_at, messages, messages_by_type,
+                 parse_errors, checksum_errors, reconnects)",
+        &column(samples, |s| s.station.clone()),
+        sampled_at,
+        &column(samples, |s| enum_name(Some(s.state))) as &[Option<String>],
+        &column(samples, |s| s.last_message_at.map(|t| t.naive_utc()))
+            as &[Option<NaiveDateTime>],
+        &column(samples, |s| s.messages as i64),
+        &column(samples, |s| json!(s.messages_by_type)),
+        &column(samples, |s| s.parse_errors as i64),
+        &column(samples, |s| s.checksum_errors as i64),
+        &column(samples, |s| s.reconnects as i64),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
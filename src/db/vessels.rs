@@ -0,0 +1,178 @@
+use super::database::{column, enum_name};
+use crate::client::envelope::MessageSource;
+use ais::messages::static_and_voyage_related_data::StaticAndVoyageRelatedData;
+use ais::messages::static_data_report::{MessagePart, StaticDataReport};
+use chrono::NaiveDateTime;
+use sqlx::PgConnection;
+use std::collections::BTreeMap;
+
+// What one batch tells us about a vessel. None means "not reported", so the
+// stored value is left alone instead of being cleared.
+#[derive(Default)]
+struct VesselUpdate {
+    vessel_name: Option<String>,
+    callsign: Option<String>,
+    imo_number: Option<i64>,
+    ship_type: Option<i32>,
+    dimensions: Option<[i32; 4]>, // To bow, stern, port and starboard
+    epfd_type: Option<String>,
+    draught: Option<f64>,
+    destination: Option<String>,
+    eta_month: Option<i32>,
+    eta_day: Option<i32>,
+    eta_hour: Option<i32>,
+    eta_minute: Option<i32>,
+    first_seen: Option<NaiveDateTime>,
+    last_seen: Option<NaiveDateTime>,
+}
+
+impl VesselUpdate {
+    fn seen(&mut self, source: &MessageSource) {
+        let at = source.received_at.naive_utc();
+        self.first_seen = Some(self.first_seen.map_or(at, |t| t.min(at)));
+        self.last_seen = Some(self.last_seen.map_or(at, |t| t.max(at)));
+    }
+}
+
+// Text fields come space/@-trimmed from the decoder, empty means not available
+fn text(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+// All four dimensions zero means the vessel doesn't report them
+fn dimensions(bow: u16, stern: u16, port: u16, starboard: u16) -> Option<[i32; 4]> {
+    let dims = [bow as i32, stern as i32, port as i32, starboard as i32];
+    (dims != [0; 4]).then_some(dims)
+}
+
+// Updates the vessel registry from static data. Several reports for the same MMSI in
+// one batch are merged first, since one upsert can't touch the same row twice.
+pub async fn upsert_vessels(
+    conn: &mut PgConnection,
+    static_voyage: &[(MessageSource, StaticAndVoyageRelatedData)],
+    static_data: &[(MessageSource, StaticDataReport)],
+) -> Result<(), sqlx::Error> {
+    let mut vessels: BTreeMap<i64, VesselUpdate> = BTreeMap::new();
+
+    for (source, sv) in static_voyage {
+        let v = vessels.entry(sv.mmsi as i64).or_default();
+        v.seen(source);
+        v.vessel_name = text(&sv.vessel_name).or(v.vessel_name.take());
+        v.callsign = text(&sv.callsign).or(v.callsign.take());
+        v.imo_number = (sv.imo_number != 0)
+            .then_some(sv.imo_number as i64)
+            .or(v.imo_number);
+        v.ship_type = sv.ship_type.map(|t| u8::from(t) as i32).or(v.ship_type);
+        v.dimensions = dimensions(
+            sv.dimension_to_bow,
+            sv.dimension_to_stern,
+            sv.dimension_to_port,
+            sv.dimension_to_starboard,
+        )
+        .or(v.dimensions);
+        v.epfd_type = enum_name(sv.epfd_type).or(v.epfd_type.take());
+        v.draught = (sv.draught > 0.0)
+            .then_some(sv.draught as f64)
+            .or(v.draught);
+        v.destination = text(&sv.destination).or(v.destination.take());
+        v.eta_month = sv.eta_month_utc.map(i32::from).or(v.eta_month);
+        v.eta_day = sv.eta_day_utc.map(i32::from).or(v.eta_day);
+        // 24 is the "not available" hour
+        v.eta_hour = (sv.eta_hour_utc < 24)
+            .then_some(sv.eta_hour_utc as i32)
+            .or(v.eta_hour);
+        v.eta_minute = sv.eta_minute_utc.map(i32::from).or(v.eta_minute);
+    }
+
+    for (source, sdr) in static_data {
+        match &sdr.message_part {
+            MessagePart::PartA { vessel_name } => {
+                let v = vessels.entry(sdr.mmsi as i64).or_default();
+                v.seen(source);
+                v.vessel_name = text(vessel_name).or(v.vessel_name.take());
+            }
+            MessagePart::PartB {
+                ship_type,
+                callsign,
+                dimension_to_bow,
+                dimension_to_stern,
+                dimension_to_port,
+                dimension_to_starboard,
+                ..
+            } => {
+                let v = vessels.entry(sdr.mmsi as i64).or_default();
+                v.seen(source);
+                v.ship_type = ship_type.map(|t| u8::from(t) as i32).or(v.ship_type);
+                v.callsign = text(callsign).or(v.callsign.take());
+                v.dimensions = dimensions(
+                    *dimension_to_bow,
+                    *dimension_to_stern,
+                    *dimension_to_port,
+                    *dimension_to_starboard,
+                )
+                .or(v.dimensions);
+            }
+            MessagePart::Unknown(_) => {}
+        }
+    }
+
+    if vessels.is_empty() {
+        return Ok(());
+    }
+
+    let vessels: Vec<_> = vessels.into_iter().collect();
+    let dimension = |i: usize| column(&vessels, move |(_, v)| v.dimensions.map(|d| d[i]));
+    // Reports replayed from the spool must not overwrite what newer ones stored
+    sqlx::query!(
+        "INSERT INTO vessels
+            (mmsi, vessel_name, callsign, imo_number, ship_type,
+             dimension_to_bow, dimension_to_stern, dimension_to_port, dimension_to_starboard,
+             epfd_type, draught, destination, eta_month, eta_day, eta_hour, eta_minute,
+             first_seen, last_seen)
+        SELECT * FROM UNNEST($1::int8[], $2::text[], $3::text[], $4::int8[], $5::int4[],
+                             $6::int4[], $7::int4[], $8::int4[], $9::int4[],
+                             $10::text[], $11::float8[], $12::text[],
+                             $13::int4[], $14::int4[], $15::int4[], $16::int4[],
+                             $17::timestamp[], $18::timestamp[])
+        ON CONFLICT (mmsi) DO UPDATE SET
+            vessel_name = COALESCE(EXCLUDED.vessel_name, vessels.vessel_name),
+            callsign = COALESCE(EXCLUDED.callsign, vessels.callsign),
+            imo_number = COALESCE(EXCLUDED.imo_number, vessels.imo_number),
+            ship_type = COALESCE(EXCLUDED.ship_type, vessels.ship_type),
+            dimension_to_bow = COALESCE(EXCLUDED.dimension_to_bow, vessels.dimension_to_bow),
+            dimension_to_stern = COALESCE(EXCLUDED.dimension_to_stern, vessels.dimension_to_stern),
+            dimension_to_port = COALESCE(EXCLUDED.dimension_to_port, vessels.dimension_to_port),
+            dimension_to_starboard = COALESCE(EXCLUDED.dimension_to_starboard, vessels.dimension_to_starboard),
+            epfd_type = COALESCE(EXCLUDED.epfd_type, vessels.epfd_type),
+            draught = COALESCE(EXCLUDED.draught, vessels.draught),
+            destination = COALESCE(EXCLUDED.destination, vessels.destination),
+            eta_month = COALESCE(EXCLUDED.eta_month, vessels.eta_month),
+            eta_day = COALESCE(EXCLUDED.eta_day, vessels.eta_day),
+            eta_hour = COALESCE(EXCLUDED.eta_hour, vessels.eta_hour),
+            eta_minute = COALESCE(EXCLUDED.eta_minute, vessels.eta_minute),
+            first_seen = LEAST(EXCLUDED.first_seen, vessels.first_seen),
+            last_seen = EXCLUDED.last_seen
+          WHERE EXCLUDED.last_seen >= vessels.last_seen",
+        &column(&vessels, |(mmsi, _)| *mmsi),
+        &column(&vessels, |(_, v)| v.vessel_name.clone()) as &[Option<String>],
+        &column(&vessels, |(_, v)| v.callsign.clone()) as &[Option<String>],
+        &column(&vessels, |(_, v)| v.imo_number) as &[Option<i64>],
+        &column(&vessels, |(_, v)| v.ship_type) as &[Option<i32>],
+        &dimension(0) as &[Option<i32>],
+        &dimension(1) as &[Option<i32>],
+        &dimension(2) as &[Option<i32>],
+        &dimension(3) as &[Option<i32>],
+        &column(&vessels, |(_, v)| v.epfd_type.clone()) as &[Option<String>],
+        &column(&vessels, |(_, v)| v.draught) as &[Option<f64>],
+        &column(&vessels, |(_, v)| v.destination.clone()) as &[Option<String>],
+        &column(&vessels, |(_, v)| v.eta_month) as &[Option<i32>],
+        &column(&vessels, |(_, v)| v.eta_day) as &[Option<i32>],
+        &column(&vessels, |(_, v)| v.eta_hour) as &[Option<i32>],
+        &column(&vessels, |(_, v)| v.eta_minute) as &[Option<i32>],
+        &column(&vessels, |(_, v)| v.first_seen) as &[Option<NaiveDateTime>],
+        &column(&vessels, |(_, v)| v.last_seen) as &[Option<NaiveDateTime>],
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,446 @@
+use super::database::{Batch, insert_batch};
+use super::spool::{self, Spool, SpooledMessage};
+use crate::client::envelope::AisEnvelope;
+use crate::config::AisConfig;
+use crate::metrics::Histogram;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Receiver;
+use tokio::time;
+
+// How often the writer prints a summary of its batch statistics
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Spooled batches replayed per flush at most, so live messages aren't held up
+// behind a long backlog
+const REPLAY_BATCHES: usize = 8;
+
+#[derive(Default)]
+pub struct WriterStats {
+    batches: AtomicU64,
+    rows: AtomicU64,
+    failed_batches: AtomicU64,
+    failed_rows: AtomicU64,
+    total_latency_us: AtomicU64,
+    last_latency_us: AtomicU64,
+    max_latency_us: AtomicU64,
+    spooled: AtomicU64,
+    replayed: AtomicU64,
+    spool_dropped: AtomicU64,
+    dead_lettered: AtomicU64,
+    latency: Histogram,
+}
+
+#[derive(Serialize)]
+pub struct WriterStatsSnapshot {
+    pub batches: u64,
+    pub rows: u64,
+    pub failed_batches: u64,
+    pub failed_rows: u64,
+    pub avg_batch_latency_ms: f64,
+    pub last_batch_latency_ms: f64,
+    pub max_batch_latency_ms: f64,
+    pub spooled: u64,
+    pub replayed: u64,
+    pub spool_dropped: u64,
+    pub dead_lettered: u64,
+}
+
+impl WriterStats {
+    fn record(&self, rows: usize, latency: Duration, ok: bool) {
+        let latency_us = latency.as_micros() as u64;
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.total_latency_us
+            .fetch_add(latency_us, Ordering::Relaxed);
+        self.last_latency_us.store(latency_us, Ordering::Relaxed);
+        self.max_latency_us.fetch_max(latency_us, Ordering::Relaxed);
+        self.latency.observe(latency);
+        if ok {
+            self.rows.fetch_add(rows as u64, Ordering::Relaxed);
+        } else {
+            self.failed_batches.fetch_add(1, Ordering::Relaxed);
+            self.failed_rows.fetch_add(rows as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    pub fn snapshot(&self) -> WriterStatsSnapshot {
+        let batches = self.batches.load(Ordering::Relaxed);
+        let total_us = self.total_latency_us.load(Ordering::Relaxed);
+        WriterStatsSnapshot {
+            batches,
+            rows: self.rows.load(Ordering::Relaxed),
+            failed_batches: self.failed_batches.load(Ordering::Relaxed),
+            failed_rows: self.failed_rows.load(Ordering::Relaxed),
+            avg_batch_latency_ms: if batches == 0 {
+                0.0
+            } else {
+                total_us as f64 / batches as f64 / 1000.0
+            },
+            last_batch_latency_ms: self.last_latency_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            max_batch_latency_ms: self.max_latency_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            spooled: self.spooled.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+            spool_dropped: self.spool_dropped.load(Ordering::Relaxed),
+            dead_lettered: self.dead_lettered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Writer {
+    pool: Arc<PgPool>,
+    config: Arc<AisConfig>,
+    stats: Arc<WriterStats>,
+    batch: Batch,
+    pending: Vec<SpooledMessage>, // Copies of what is in `batch`, to spool or split it
+    spool: Option<Spool>,
+    retry_at: Option<Instant>, // The database is treated as down until then
+    replaying: bool,
+}
+
+// Collects envelopes from the pipeline and writes them in batches,
+// flushing when `batch_size` messages are queued or every `batch_interval`
+pub async fn run(
+    pool: Arc<PgPool>,
+    mut rx: Receiver<AisEnvelope>,
+    config: Arc<AisConfig>,
+    stats: Arc<WriterStats>,
+) {
+    let spool = match &config.spool {
+        Some(spool_config) => match Spool::open(spool_config).await {
+            Ok(spool) => Some(spool),
+            Err(e) => {
+                eprintln!(
+                    "Failed to open spool {}, continuing without it: {}",
+                    spool_config.path.display(),
+                    e
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut flush_tick = time::interval(config.batch_interval);
+    let mut report_tick = time::interval(REPORT_INTERVAL);
+    let mut writer = Writer {
+        pool,
+        config,
+        stats,
+        batch: Batch::default(),
+        pending: Vec::new(),
+        spool,
+        retry_at: None,
+        replaying: false,
+    };
+
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Some(envelope) => {
+                    writer.push(envelope);
+                    if writer.batch.len() >= writer.config.batch_size {
+                        writer.flush().await;
+                        flush_tick.reset();
+                    }
+                }
+                None => break,
+            },
+            _ = flush_tick.tick() => writer.flush().await,
+            _ = report_tick.tick() => {
+                let s = writer.stats.snapshot();
+                println!(
+                    "Writer: {} rows in {} batches, avg {:.1} ms, max {:.1} ms, {} failed batches, {} spooled, {} replayed, {} dead-lettered",
+                    s.rows, s.batches, s.avg_batch_latency_ms, s.max_batch_latency_ms, s.failed_batches, s.spooled, s.replayed, s.dead_lettered
+                );
+            }
+        }
+    }
+
+    // The pipeline has shut down, write out what is left
+    writer.flush().await;
+}
+
+// Errors meaning the database can't be reached right now, as opposed to one
+// refusing the statement or some of its rows
+fn unreachable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed => true,
+        // Connection exceptions, the server shutting down or still starting up, and a
+        // standby refusing writes after a failover
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            code.starts_with("08") || matches!(&*code, "57P01" | "57P02" | "57P03" | "25006")
+        }),
+        _ => false,
+    }
+}
+
+// Messages that didn't go in because the database is unreachable. Those before
+// `written` did, before it went away.
+struct Unreachable {
+    written: usize,
+    error: sqlx::Error,
+}
+
+impl Writer {
+    fn push(&mut self, envelope: AisEnvelope) {
+        self.pending.push(SpooledMessage::from_envelope(&envelope));
+        self.batch.push(envelope);
+    }
+
+    async fn flush(&mut self) {
+        let spool_waiting = self.spool.as_ref().is_some_and(|s| !s.is_empty());
+        if self.batch.is_empty() && !spool_waiting {
+            return;
+        }
+
+        let batch = std::mem::take(&mut self.batch);
+        let pending = std::mem::take(&mut self.pending);
+
+        if self.spool.is_some() {
+            // Don't hammer a database that just failed, keep spooling until the retry time
+            if self.retry_at.is_some_and(|at| Instant::now() < at) {
+                self.spool(&pending).await;
+                return;
+            }
+            // Older spooled messages have to go in before this batch to keep the order.
+            // A few batches of them are written per flush; while more are left, this
+            // batch is spooled behind them.
+            if spool_waiting {
+                match self.replay().await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        self.spool(&pending).await;
+                        return;
+                    }
+                    Err(e) => {
+                        eprintln!("Database still unavailable, spooling: {}", e);
+                        self.database_failed();
+                        self.spool(&pending).await;
+                        return;
+                    }
+                }
+            }
+        }
+
+        if batch.is_empty() {
+            return;
+        }
+
+        match self.write(batch, &pending).await {
+            Ok(()) => self.retry_at = None,
+            Err(Unreachable { written, error }) if self.spool.is_some() => {
+                eprintln!(
+                    "Failed to insert {} messages, spooling: {}",
+                    pending.len() - written,
+                    error
+                );
+                self.database_failed();
+                self.spool(&pending[written..]).await;
+            }
+            Err(Unreachable { written, error }) => eprintln!(
+                "Failed to insert {} messages: {}",
+                pending.len() - written,
+                error
+            ),
+        }
+    }
+
+    async fn insert(&self, batch: &Batch) -> Result<(), sqlx::Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let result = insert_batch(&self.pool, batch).await;
+        self.stats
+            .record(batch.len(), started.elapsed(), result.is_ok());
+        result
+    }
+
+    // Writes `batch`, whose messages are `messages`. When the database refuses it,
+    // the batch is halved until the rows at fault are on their own; those go to the
+    // dead-letter file and everything else is written.
+    async fn write(&self, batch: Batch, messages: &[SpooledMessage]) -> Result<(), Unreachable> {
+        let error = match self.insert(&batch).await {
+            Ok(()) => return Ok(()),
+            Err(error) if unreachable(&error) => return Err(Unreachable { written: 0, error }),
+            Err(error) => error,
+        };
+        eprintln!(
+            "Batch of {} messages rejected, looking for the bad rows: {}",
+            messages.len(),
+            error
+        );
+
+        // Messages that don't decode are dead-lettered here once and left out of
+        // every part below
+        let mut decodes = Vec::with_capacity(messages.len());
+        for message in messages {
+            let result = message.clone().into_envelope();
+            if let Err(e) = &result {
+                self.dead_letter(message, &format!("{:#}", e)).await;
+            }
+            decodes.push(result.is_ok());
+        }
+
+        // Halves go on the stack second-first, so they are written in order and
+        // what has been written is always the front of `messages`
+        let mut parts = vec![(0..messages.len(), Some(error))];
+        let mut written = 0;
+        while let Some((part, failed)) = parts.pop() {
+            let error = match failed {
+                Some(error) => error,
+                None => {
+                    let mut batch = Batch::default();
+                    for (message, _) in messages[part.clone()]
+                        .iter()
+                        .zip(&decodes[part.clone()])
+                        .filter(|(_, decodes)| **decodes)
+                    {
+                        if let Ok(envelope) = message.clone().into_envelope() {
+                            batch.push(envelope);
+                        }
+                    }
+                    match self.insert(&batch).await {
+                        Ok(()) => {
+                            written += part.len();
+                            continue;
+                        }
+                        Err(error) if unreachable(&error) => {
+                            return Err(Unreachable { written, error });
+                        }
+                        Err(error) => error,
+                    }
+                }
+            };
+            if part.len() == 1 {
+                self.dead_letter(&messages[part.start], &error.to_string())
+                    .await;
+                written += 1;
+                continue;
+            }
+            let middle = part.start + part.len() / 2;
+            parts.push((middle..part.end, None));
+            parts.push((part.start..middle, None));
+        }
+        Ok(())
+    }
+
+    async fn dead_letter(&self, message: &SpooledMessage, error: &str) {
+        let path = &self.config.dead_letter;
+        match spool::dead_letter(path, message, error).await {
+            Ok(()) => eprintln!(
+                "Moved a message from {} to {}: {}",
+                message.source.station,
+                path.display(),
+                error
+            ),
+            Err(e) => eprintln!("Failed to write {}, message lost: {}", path.display(), e),
+        }
+        self.stats.dead_lettered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn database_failed(&mut self) {
+        if let Some(spool_config) = &self.config.spool {
+            self.retry_at = Some(Instant::now() + spool_config.retry_interval);
+        }
+    }
+
+    async fn spool(&mut self, messages: &[SpooledMessage]) {
+        let Some(spool) = &mut self.spool else {
+            return;
+        };
+        if messages.is_empty() {
+            return;
+        }
+        match spool.append(messages).await {
+            Ok(dropped) => {
+                let spooled = (messages.len() - dropped.min(messages.len())) as u64;
+                self.stats.spooled.fetch_add(spooled, Ordering::Relaxed);
+                if dropped > 0 {
+                    self.stats
+                        .spool_dropped
+                        .fetch_add(dropped as u64, Ordering::Relaxed);
+                    eprintln!("Spool is full, dropped {} messages", dropped);
+                }
+            }
+            Err(e) => eprintln!(
+                "Failed to spool {} messages, they are lost: {}",
+                messages.len(),
+                e
+            ),
+        }
+    }
+
+    // Writes up to REPLAY_BATCHES batches from the spool to the database, oldest
+    // first, and returns whether the spool is empty now. On failure the spool keeps
+    // what has not been written yet.
+    async fn replay(&mut self) -> anyhow::Result<bool> {
+        let Some(mut spool) = self.spool.take() else {
+            return Ok(true);
+        };
+        if !self.replaying {
+            println!("Replaying spooled messages");
+            self.replaying = true;
+        }
+        let result = self.replay_from(&mut spool).await;
+        if let Ok(true) = result {
+            println!("Spool replayed, back to writing directly");
+            self.replaying = false;
+        }
+        self.spool = Some(spool);
+        result
+    }
+
+    async fn replay_from(&self, spool: &mut Spool) -> anyhow::Result<bool> {
+        let chunk_size = self.config.batch_size.max(1);
+        for _ in 0..REPLAY_BATCHES {
+            let Some(lines) = spool.next_lines(chunk_size).await? else {
+                return Ok(true);
+            };
+            let mut batch = Batch::default();
+            let mut messages = Vec::new();
+            let mut line_of = Vec::new(); // Lines up to and including each message
+            for (index, line) in lines.iter().enumerate() {
+                if line.is_empty() {
+                    continue;
+                }
+                let message = match serde_json::from_str::<SpooledMessage>(line) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        eprintln!("Skipping unreadable spooled message: {}", e);
+                        continue;
+                    }
+                };
+                match message.clone().into_envelope() {
+                    Ok(envelope) => batch.push(envelope),
+                    Err(e) => {
+                        eprintln!("Skipping unreadable spooled message: {}", e);
+                        continue;
+                    }
+                }
+                messages.push(message);
+                line_of.push(index + 1);
+            }
+
+            if let Err(Unreachable { written, error }) = self.write(batch, &messages).await {
+                let written_lines = written.checked_sub(1).map_or(0, |last| line_of[last]);
+                spool.rewind(lines.len() - written_lines).await?;
+                return Err(error.into());
+            }
+            self.stats
+                .replayed
+                .fetch_add(messages.len() as u64, Ordering::Relaxed);
+        }
+        Ok(spool.is_empty())
+    }
+}
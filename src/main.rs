@@ -1,35 +1,66 @@
 mod ais;
+mod alarms;
+mod auth;
 mod client;
 mod config;
 mod db;
+mod metrics;
+mod server;
+use anyhow::bail;
 use dotenvy::dotenv;
 use sqlx::PgPool;
+use sqlx::postgres::PgPoolOptions;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::client::connection::AisConnectionManager;
-use crate::config::AisConfig;
-use axum::{Json, Router, http::StatusCode, routing::get};
-use chrono::NaiveDateTime;
-use serde::Serialize;
+use crate::alarms::notifier::LogNotifier;
+use crate::client::supervisor::StationError;
+use crate::config::StationFile;
+use axum::{
+    Json, Router,
+    extract::Path,
+    http::{StatusCode, header},
+    middleware,
+    routing::{delete, get, post},
+};
 use sqlx::FromRow;
-use std::net::SocketAddr;
 
 #[derive(serde::Serialize, FromRow)]
 struct AisPosition {
     mmsi: i64,
+    vessel_name: Option<String>,
     latitude: f64,
     longitude: f64,
     received_at: Option<sqlx::types::chrono::NaiveDateTime>,
 }
 
+fn database_error(e: sqlx::Error) -> (StatusCode, String) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Database error: {}", e),
+    )
+}
+
+fn station_error(e: StationError) -> (StatusCode, String) {
+    let status = match e {
+        StationError::Unknown(_) => StatusCode::NOT_FOUND,
+        StationError::Exists(_) => StatusCode::CONFLICT,
+        StationError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    (status, e.to_string())
+}
+
 async fn get_positions(pool: Arc<PgPool>) -> Json<Vec<AisPosition>> {
     // Query to get the positions from the database
     let positions = sqlx::query_as!(
         AisPosition, // The type to map the results to
         r#"
-        SELECT mmsi, latitude, longitude, received_at
-        FROM ais_position_reports
+        SELECT p.mmsi, v.vessel_name as "vessel_name?", p.latitude as "latitude!",
+               p.longitude as "longitude!", p.received_at
+        FROM ais_position_reports p
+        LEFT JOIN vessels v ON v.mmsi = p.mmsi
+        WHERE p.latitude IS NOT NULL AND p.longitude IS NOT NULL
         LIMIT 10
         "#,
     )
@@ -48,68 +79,421 @@ async fn get_last_positions(
     let positions = sqlx::query_as!(
         AisPosition, // The type to map the results to
         r#"
-        SELECT DISTINCT ON (mmsi) mmsi, latitude, longitude, received_at
-        FROM ais_position_reports
-        ORDER BY mmsi, received_at DESC;
+        SELECT DISTINCT ON (p.mmsi) p.mmsi, v.vessel_name as "vessel_name?",
+               p.latitude as "latitude!", p.longitude as "longitude!", p.received_at
+        FROM ais_position_reports p
+        LEFT JOIN vessels v ON v.mmsi = p.mmsi
+        WHERE p.latitude IS NOT NULL AND p.longitude IS NOT NULL
+        ORDER BY p.mmsi, p.received_at DESC;
         "#,
     )
     .fetch_all(&*pool) // Dereference the Arc to pass a reference to PgPool
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Database error: {}", e),
-        )
-    })?;
+    .map_err(database_error)?;
 
     Ok(Json(positions)) // Return the results as JSON
 }
 
+#[derive(serde::Serialize, FromRow)]
+struct Vessel {
+    mmsi: i64,
+    vessel_name: Option<String>,
+    callsign: Option<String>,
+    imo_number: Option<i64>,
+    ship_type: Option<i32>,
+    dimension_to_bow: Option<i32>,
+    dimension_to_stern: Option<i32>,
+    dimension_to_port: Option<i32>,
+    dimension_to_starboard: Option<i32>,
+    epfd_type: Option<String>,
+    draught: Option<f64>,
+    destination: Option<String>,
+    eta_month: Option<i32>,
+    eta_day: Option<i32>,
+    eta_hour: Option<i32>,
+    eta_minute: Option<i32>,
+    first_seen: sqlx::types::chrono::NaiveDateTime,
+    last_seen: sqlx::types::chrono::NaiveDateTime,
+}
+
+#[derive(serde::Serialize, FromRow)]
+struct VesselChange {
+    field: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    changed_at: sqlx::types::chrono::NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+struct VesselDetails {
+    #[serde(flatten)]
+    vessel: Vessel,
+    history: Vec<VesselChange>,
+}
+
+// All known vessels, most recently heard first
+async fn get_vessels(pool: Arc<PgPool>) -> Result<Json<Vec<Vessel>>, (StatusCode, String)> {
+    let vessels = sqlx::query_as!(Vessel, "SELECT * FROM vessels ORDER BY last_seen DESC")
+        .fetch_all(&*pool)
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(vessels))
+}
+
+// One vessel with the history of its static and voyage data
+async fn get_vessel(
+    pool: Arc<PgPool>,
+    mmsi: i64,
+) -> Result<Json<VesselDetails>, (StatusCode, String)> {
+    let vessel = sqlx::query_as!(Vessel, "SELECT * FROM vessels WHERE mmsi = $1", mmsi)
+        .fetch_optional(&*pool)
+        .await
+        .map_err(database_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Unknown vessel {}", mmsi)))?;
+
+    let history = sqlx::query_as!(
+        VesselChange,
+        r#"
+        SELECT field, old_value, new_value, changed_at
+        FROM vessel_history
+        WHERE mmsi = $1
+        ORDER BY changed_at DESC, id DESC
+        "#,
+        mmsi
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(database_error)?;
+
+    Ok(Json(VesselDetails { vessel, history }))
+}
+
+#[derive(serde::Serialize, FromRow)]
+struct AtonStatus {
+    mmsi: i64,
+    name: String,
+    aid_type: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    off_position: bool,
+    virtual_aid: bool,
+    status_page: Option<i32>,
+    racon_status: Option<String>,
+    light_status: Option<String>,
+    health_status: Option<String>,
+    first_seen: sqlx::types::chrono::NaiveDateTime,
+    last_seen: sqlx::types::chrono::NaiveDateTime,
+}
+
+#[derive(serde::Serialize, FromRow)]
+struct AtonStatusChange {
+    off_position: bool,
+    racon_status: Option<String>,
+    light_status: Option<String>,
+    health_status: Option<String>,
+    changed_at: sqlx::types::chrono::NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+struct AtonDetails {
+    #[serde(flatten)]
+    status: AtonStatus,
+    history: Vec<AtonStatusChange>,
+}
+
+// Current status of every aid to navigation
+async fn get_atons(pool: Arc<PgPool>) -> Result<Json<Vec<AtonStatus>>, (StatusCode, String)> {
+    let atons = sqlx::query_as!(AtonStatus, "SELECT * FROM aton_status ORDER BY name")
+        .fetch_all(&*pool)
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(atons))
+}
+
+// One aid to navigation with its status changes, newest first
+async fn get_aton(pool: Arc<PgPool>, mmsi: i64) -> Result<Json<AtonDetails>, (StatusCode, String)> {
+    let status = sqlx::query_as!(
+        AtonStatus,
+        "SELECT * FROM aton_status WHERE mmsi = $1",
+        mmsi
+    )
+    .fetch_optional(&*pool)
+    .await
+    .map_err(database_error)?
+    .ok_or((StatusCode::NOT_FOUND, format!("Unknown AtoN {}", mmsi)))?;
+
+    let history = sqlx::query_as!(
+        AtonStatusChange,
+        r#"
+        SELECT off_position, racon_status, light_status, health_status, changed_at
+        FROM aton_status_history
+        WHERE mmsi = $1
+        ORDER BY changed_at DESC, id DESC
+        "#,
+        mmsi
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(database_error)?;
+
+    Ok(Json(AtonDetails { status, history }))
+}
+
+#[derive(serde::Serialize, FromRow)]
+struct StationStatsRow {
+    sampled_at: sqlx::types::chrono::NaiveDateTime,
+    state: String,
+    last_message_at: Option<sqlx::types::chrono::NaiveDateTime>,
+    messages: i64,
+    messages_by_type: serde_json::Value,
+    parse_errors: i64,
+    checksum_errors: i64,
+    reconnects: i64,
+}
+
+// A station's reception over the last 24 hours, oldest sample first
+async fn get_station_history(
+    pool: Arc<PgPool>,
+    station: String,
+) -> Result<Json<Vec<StationStatsRow>>, (StatusCode, String)> {
+    let rows = sqlx::query_as!(
+        StationStatsRow,
+        r#"
+        SELECT sampled_at, state, last_message_at, messages, messages_by_type,
+               parse_errors, checksum_errors, reconnects
+        FROM station_stats
+        WHERE station = $1 AND sampled_at > NOW() AT TIME ZONE 'UTC' - INTERVAL '24 hours'
+        ORDER BY sampled_at
+        "#,
+        station
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(database_error)?;
+
+    Ok(Json(rows))
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL not found in .env");
-    let pool = PgPool::connect(&database_url).await.unwrap();
+    let settings = config::load()?;
+    // Connect lazily so we can start and spool to disk while the database is down
+    let pool = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(5))
+        .connect_lazy(&settings.database_url)?;
     let pool = Arc::new(pool);
-    // Create configuration with multiple endpoints
-    let config = AisConfig {
-        endpoints: vec![
-            "192.168.55.161:4712".into(), // Labinstica
-            "192.168.52.161:4712".into(), // VDG
-            "192.168.61.161:4712".into(), // ucka
-            "192.168.66.161:4712".into(), // osor
-        ],
-        ..Default::default()
-    };
 
-    // Instantiate the connection manager
-    //let mut manager = AisConnectionManager::new(config);
-    let mut client = client::AisClient::new(config);
+    // `aismar replay [options] FILE...` back-fills from recorded NMEA instead of listening
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "replay") {
+        let options = client::replay::ReplayOptions::from_args(&args[1..])?;
+        return client::AisClient::new(settings.ais)
+            .replay(pool, options)
+            .await;
+    }
+    if settings.ais.endpoints.is_empty() {
+        bail!("No stations configured; add [[stations]] to aismar.toml or AISMAR_CONFIG");
+    }
+
+    let mut client = client::AisClient::new(settings.ais);
+
     client.run(pool.clone()).await?;
-    // Start the connection manager
-    //manager.start().await?;
+    let writer_stats = client.writer_stats();
+    let fragment_stats = client.fragment_stats();
+    let stations = client.stations();
+    let http_metrics = Arc::new(metrics::HttpMetrics::default());
+    let metric_sources = Arc::new(metrics::MetricSources {
+        stations: stations.clone(),
+        writer: writer_stats.clone(),
+        fragments: fragment_stats.clone(),
+        queues: client.queues(),
+        http: http_metrics.clone(),
+    });
+    let supervisor = client.supervisor().expect("connections are started by run");
+    let alarms = client.alarms();
+    alarms.add_notifier(Box::new(LogNotifier));
+
+    // Station management, e.g. pausing a receiver from a dashboard during maintenance,
+    // and acknowledging alarms
+    let admin = Router::new()
+        .route(
+            "/admin/stations",
+            get({
+                let supervisor = supervisor.clone();
+                move || async move { Json(supervisor.list()) }
+            })
+            .post({
+                let supervisor = supervisor.clone();
+                move |Json(station): Json<StationFile>| async move {
+                    supervisor
+                        .add(station)
+                        .await
+                        .map(|status| (StatusCode::CREATED, Json(status)))
+                        .map_err(station_error)
+                }
+            }),
+        )
+        .route(
+            "/admin/stations/{station}",
+            delete({
+                let supervisor = supervisor.clone();
+                move |Path(station): Path<String>| async move {
+                    supervisor
+                        .remove(&station)
+                        .await
+                        .map(|()| StatusCode::NO_CONTENT)
+                        .map_err(station_error)
+                }
+            }),
+        )
+        .route(
+            "/admin/stations/{station}/pause",
+            post({
+                let supervisor = supervisor.clone();
+                move |Path(station): Path<String>| async move {
+                    supervisor
+                        .pause(&station)
+                        .await
+                        .map(Json)
+                        .map_err(station_error)
+                }
+            }),
+        )
+        .route(
+            "/admin/stations/{station}/resume",
+            post({
+                let supervisor = supervisor.clone();
+                move |Path(station): Path<String>| async move {
+                    supervisor
+                        .resume(&station)
+                        .await
+                        .map(Json)
+                        .map_err(station_error)
+                }
+            }),
+        )
+        .route(
+            "/alarms/{id}/ack",
+            post({
+                let alarms = alarms.clone();
+                move |Path(id): Path<u64>| async move {
+                    alarms
+                        .acknowledge(id)
+                        .map(Json)
+                        .ok_or((StatusCode::NOT_FOUND, format!("No open alarm {}", id)))
+                }
+            }),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            settings.api.token.as_deref().map(Arc::from),
+            auth::require_token,
+        ));
 
     // Define the Axum application with the route
     let app = Router::new()
-        .route("/positions", get({
-            let pool = pool.clone(); // Clone the Arc to move into the closure
-            move || async move { get_positions(pool).await }
-        }),)
-        .route("/last_positions", get({
-            let pool = pool.clone(); 
-            move || async move { get_last_positions(pool).await }
-        }));
-
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+        .route(
+            "/positions",
+            get({
+                let pool = pool.clone(); // Clone the Arc to move into the closure
+                move || async move { get_positions(pool).await }
+            }),
+        )
+        .route(
+            "/last_positions",
+            get({
+                let pool = pool.clone();
+                move || async move { get_last_positions(pool).await }
+            }),
+        )
+        .route(
+            "/vessels",
+            get({
+                let pool = pool.clone();
+                move || async move { get_vessels(pool).await }
+            }),
+        )
+        .route(
+            "/vessels/{mmsi}",
+            get({
+                let pool = pool.clone();
+                move |Path(mmsi): Path<i64>| async move { get_vessel(pool, mmsi).await }
+            }),
+        )
+        .route(
+            "/atons",
+            get({
+                let pool = pool.clone();
+                move || async move { get_atons(pool).await }
+            }),
+        )
+        .route(
+            "/atons/{mmsi}",
+            get({
+                let pool = pool.clone();
+                move |Path(mmsi): Path<i64>| async move { get_aton(pool, mmsi).await }
+            }),
+        )
+        .route(
+            "/alarms",
+            get({
+                let alarms = alarms.clone();
+                move || async move { Json(alarms.open()) }
+            }),
+        )
+        .route(
+            "/alarms/cleared",
+            get({
+                let alarms = alarms.clone();
+                move || async move { Json(alarms.cleared()) }
+            }),
+        )
+        .route(
+            "/stats/writer",
+            get(move || async move { Json(writer_stats.snapshot()) }),
+        )
+        .route(
+            "/stats/fragments",
+            get(move || async move { Json(fragment_stats.snapshot()) }),
+        )
+        .route(
+            "/stations",
+            get(move || async move { Json(stations.snapshot()) }),
+        )
+        .route(
+            "/stations/{station}/history",
+            get({
+                let pool = pool.clone();
+                move |Path(station): Path<String>| async move {
+                    get_station_history(pool, station).await
+                }
+            }),
+        )
+        .route(
+            "/metrics",
+            get(move || async move {
+                (
+                    [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                    metrics::render(&metric_sources),
+                )
+            }),
+        )
+        .merge(admin)
+        .route_layer(middleware::from_fn_with_state(
+            http_metrics,
+            metrics::track_requests,
+        ));
+
+    // run our app with hyper on the configured address
+    let listener = tokio::net::TcpListener::bind(&settings.api.bind).await?;
     axum::serve(listener, app).await.unwrap();
 
     // Wait for Ctrl+C signal to gracefully shut down
     tokio::signal::ctrl_c().await?;
     println!("Shutting down...");
 
-    // Shutdown the connection manager
-    //manager.shutdown().await;
     client.shutdown().await;
 
     Ok(())
@@ -0,0 +1,338 @@
+use crate::ais::fragments::FragmentStats;
+use crate::client::envelope::AisEnvelope;
+use crate::client::stats::{ConnectionState, StationRegistry};
+use crate::db::writer::WriterStats;
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Sender, WeakSender};
+
+// Bucket bounds in seconds, from a fast insert or request up to a stuck one
+const LATENCY_BUCKETS: [f64; 13] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Latency histogram with Prometheus' cumulative `le` buckets
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut Exposition, name: &str, labels: &[(&str, &str)]) {
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            let le = bound.to_string();
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &le));
+            let value = bucket.load(Ordering::Relaxed) as f64;
+            out.sample(&format!("{}_bucket", name), &bucket_labels, value);
+        }
+        let count = self.count.load(Ordering::Relaxed) as f64;
+        let mut inf_labels = labels.to_vec();
+        inf_labels.push(("le", "+Inf"));
+        out.sample(&format!("{}_bucket", name), &inf_labels, count);
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        out.sample(&format!("{}_sum", name), labels, sum);
+        out.sample(&format!("{}_count", name), labels, count);
+    }
+}
+
+// The queues between pipeline stages, held weakly so they still close on shutdown
+#[derive(Default)]
+pub struct Queues {
+    queues: Mutex<Vec<(&'static str, WeakSender<AisEnvelope>)>>,
+}
+
+impl Queues {
+    pub fn watch(&self, name: &'static str, tx: &Sender<AisEnvelope>) {
+        self.queues.lock().unwrap().push((name, tx.downgrade()));
+    }
+
+    // Messages waiting in every queue that is still open
+    pub fn depths(&self) -> Vec<(&'static str, usize)> {
+        self.queues
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, tx)| {
+                let tx = tx.upgrade()?;
+                Some((*name, tx.max_capacity() - tx.capacity()))
+            })
+            .collect()
+    }
+}
+
+// Request latency of every API route
+#[derive(Default)]
+pub struct HttpMetrics {
+    routes: Mutex<BTreeMap<(String, String), Arc<Histogram>>>,
+}
+
+// Middleware timing each request; labelled with the route pattern, not the path,
+// so /vessels/{mmsi} stays one series
+pub async fn track_requests(
+    State(http): State<Arc<HttpMetrics>>,
+    path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let path = path.map_or("unmatched".to_string(), |p| p.as_str().to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let histogram = http
+        .routes
+        .lock()
+        .unwrap()
+        .entry((method, path))
+        .or_default()
+        .clone();
+    histogram.observe(started.elapsed());
+    response
+}
+
+// Builds the text exposition format, one family at a time
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub struct MetricSources {
+    pub stations: Arc<StationRegistry>,
+    pub writer: Arc<WriterStats>,
+    pub fragments: Arc<FragmentStats>,
+    pub queues: Arc<Queues>,
+    pub http: Arc<HttpMetrics>,
+}
+
+pub fn render(sources: &MetricSources) -> String {
+    let mut out = Exposition { out: String::new() };
+    let stations = sources.stations.snapshot();
+
+    out.family(
+        "aismar_lines_read_total",
+        "counter",
+        "Lines read from each endpoint",
+    );
+    for s in &stations {
+        out.sample(
+            "aismar_lines_read_total",
+            &[("station", &s.station)],
+            s.lines as f64,
+        );
+    }
+
+    out.family(
+        "aismar_decode_errors_total",
+        "counter",
+        "Lines that could not be decoded, by kind",
+    );
+    for s in &stations {
+        out.sample(
+            "aismar_decode_errors_total",
+            &[("station", &s.station), ("kind", "checksum")],
+            s.checksum_errors as f64,
+        );
+        out.sample(
+            "aismar_decode_errors_total",
+            &[("station", &s.station), ("kind", "parse")],
+            s.parse_errors as f64,
+        );
+    }
+
+    out.family(
+        "aismar_messages_total",
+        "counter",
+        "Decoded messages by station and message type",
+    );
+    for s in &stations {
+        for (message_type, count) in &s.messages_by_type {
+            let message_type = message_type.to_string();
+            out.sample(
+                "aismar_messages_total",
+                &[("station", &s.station), ("type", &message_type)],
+                *count as f64,
+            );
+        }
+    }
+
+    out.family(
+        "aismar_station_connected",
+        "gauge",
+        "1 while the endpoint is connected",
+    );
+    for s in &stations {
+        let connected = s.state == ConnectionState::Connected;
+        out.sample(
+            "aismar_station_connected",
+            &[("station", &s.station)],
+            connected as u8 as f64,
+        );
+    }
+
+    out.family(
+        "aismar_station_reconnects_total",
+        "counter",
+        "Reconnect attempts per endpoint",
+    );
+    for s in &stations {
+        out.sample(
+            "aismar_station_reconnects_total",
+            &[("station", &s.station)],
+            s.reconnects as f64,
+        );
+    }
+
+    out.family(
+        "aismar_station_last_message_timestamp_seconds",
+        "gauge",
+        "When each endpoint last delivered a message",
+    );
+    for s in &stations {
+        if let Some(at) = s.last_message_at {
+            out.sample(
+                "aismar_station_last_message_timestamp_seconds",
+                &[("station", &s.station)],
+                at.timestamp_millis() as f64 / 1000.0,
+            );
+        }
+    }
+
+    let fragments = sources.fragments.snapshot();
+    out.family(
+        "aismar_fragments_total",
+        "counter",
+        "Multi-sentence messages completed, and fragments orphaned or expired",
+    );
+    for (outcome, count) in [
+        ("completed", fragments.completed),
+        ("orphaned", fragments.orphaned),
+        ("expired", fragments.expired),
+    ] {
+        out.sample(
+            "aismar_fragments_total",
+            &[("outcome", outcome)],
+            count as f64,
+        );
+    }
+
+    out.family(
+        "aismar_queue_depth",
+        "gauge",
+        "Messages waiting between pipeline stages",
+    );
+    for (queue, depth) in sources.queues.depths() {
+        out.sample("aismar_queue_depth", &[("queue", queue)], depth as f64);
+    }
+
+    let writer = sources.writer.snapshot();
+    out.family(
+        "aismar_db_rows_written_total",
+        "counter",
+        "Rows written to the database",
+    );
+    out.sample("aismar_db_rows_written_total", &[], writer.rows as f64);
+    out.family(
+        "aismar_db_failed_batches_total",
+        "counter",
+        "Batches the database rejected",
+    );
+    out.sample(
+        "aismar_db_failed_batches_total",
+        &[],
+        writer.failed_batches as f64,
+    );
+    out.family(
+        "aismar_spooled_messages_total",
+        "counter",
+        "Messages spooled to disk while the database was down",
+    );
+    out.sample("aismar_spooled_messages_total", &[], writer.spooled as f64);
+    out.family(
+        "aismar_dead_letter_messages_total",
+        "counter",
+        "Messages the database refused, moved to the dead-letter file",
+    );
+    out.sample(
+        "aismar_dead_letter_messages_total",
+        &[],
+        writer.dead_lettered as f64,
+    );
+    out.family(
+        "aismar_db_insert_duration_seconds",
+        "histogram",
+        "Time to write one batch",
+    );
+    sources
+        .writer
+        .latency()
+        .write(&mut out, "aismar_db_insert_duration_seconds", &[]);
+
+    out.family(
+        "aismar_http_request_duration_seconds",
+        "histogram",
+        "API request latency by route",
+    );
+    for ((method, path), histogram) in sources.http.routes.lock().unwrap().iter() {
+        histogram.write(
+            &mut out,
+            "aismar_http_request_duration_seconds",
+            &[("method", method), ("path", path)],
+        );
+    }
+
+    out.out
+}
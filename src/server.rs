@@ -1,14 +1,89 @@
-use tokio::net::TcpListener;
+use crate::ais::fragments::to_sentences;
+use crate::client::envelope::AisEnvelope;
 use crate::config::ServerConfig;
-use crate::client::connection;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{Receiver, Sender};
 
-pub async fn run(config: ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind(&config.address).await?;
-    println!("Server listening on {}", config.address);
+// Lines going out to downstream clients. Sending never waits on a client; one that
+// falls more than the channel capacity behind skips ahead instead.
+pub type Feed = broadcast::Sender<Arc<str>>;
 
+pub fn feed(config: Option<&ServerConfig>) -> Feed {
+    let capacity = config.map_or(1, |c| c.client_buffer.max(1));
+    broadcast::channel(capacity).0
+}
+
+// Accepts clients on every configured address; each gets the raw or filtered feed
+pub async fn run(config: ServerConfig, raw: Feed, filtered: Feed) -> anyhow::Result<()> {
+    let mut listeners = Vec::new();
+    for (address, feed, kind) in [
+        (&config.raw_address, raw, "raw"),
+        (&config.filtered_address, filtered, "filtered"),
+    ] {
+        if let Some(address) = address {
+            let listener = TcpListener::bind(address).await?;
+            println!("Server listening on {} ({})", address, kind);
+            listeners.push(tokio::spawn(accept(listener, feed, kind)));
+        }
+    }
+
+    for listener in listeners {
+        listener.await??;
+    }
+    Ok(())
+}
+
+async fn accept(listener: TcpListener, feed: Feed, kind: &'static str) -> anyhow::Result<()> {
     loop {
         let (socket, addr) = listener.accept().await?;
-        println!("New connection from: {}", addr);
-        tokio::spawn(connection::handle_connection(socket));
+        println!("New {} client: {}", kind, addr);
+        let lines = feed.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = send_lines(socket, lines).await {
+                println!("{} client {} disconnected: {}", kind, addr, e);
+            }
+        });
+    }
+}
+
+async fn send_lines(
+    mut socket: TcpStream,
+    mut lines: broadcast::Receiver<Arc<str>>,
+) -> anyhow::Result<()> {
+    loop {
+        match lines.recv().await {
+            Ok(line) => {
+                socket.write_all(line.as_bytes()).await?;
+                socket.write_all(b"\r\n").await?;
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                eprintln!("Slow client skipped {} lines", skipped);
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+// Pipeline stage after deduplication: re-armors each message for the filtered feed
+pub async fn forward(mut rx: Receiver<AisEnvelope>, tx: Sender<AisEnvelope>, filtered: Feed) {
+    let mut message_id = 0;
+    while let Some(envelope) = rx.recv().await {
+        if filtered.receiver_count() > 0 {
+            for sentence in to_sentences(
+                &envelope.payload,
+                envelope.fill_bits,
+                &envelope.framing,
+                message_id,
+            ) {
+                let _ = filtered.send(sentence.into());
+            }
+            message_id = (message_id + 1) % 10;
+        }
+        if tx.send(envelope).await.is_err() {
+            return;
+        }
     }
 }